@@ -2,16 +2,32 @@ extern crate fixray;
 extern crate termion;
 
 use fixray::*;
+use fixray::palette::Palette;
 
-struct TermDriver;
+/// Terminal with either 24-bit color or the xterm palette.
+struct TermDriver {
+    truecolor: bool,
+}
 
 impl Driver for TermDriver {
+    type Palette = palette::Xterm256;
+
+    fn palette(&self) -> &palette::Xterm256 {
+        &palette::Xterm256
+    }
+
     fn screen_size(&self) -> (u32, u32) {
         (64, 64)
     }
 
     fn draw_screen<F>(&self, pixel_f: F)
         where F: Fn(u32, u32) -> Color
+    {
+        self.draw_screen_rgb(|x, y| Rgb::from(pixel_f(x, y)))
+    }
+
+    fn draw_screen_rgb<F>(&self, pixel_f: F)
+        where F: Fn(u32, u32) -> Rgb
     {
         let (w, h) = self.screen_size();
         let h = h / 2;
@@ -22,27 +38,17 @@ impl Driver for TermDriver {
 
                 use termion::color;
 
-                match back {
-                    Color::Black => print!("{}", color::Bg(color::Black)),
-                    Color::Red => print!("{}", color::Bg(color::Red)),
-                    Color::Green => print!("{}", color::Bg(color::Green)),
-                    Color::Yellow => print!("{}", color::Bg(color::Yellow)),
-                    Color::Blue => print!("{}", color::Bg(color::Blue)),
-                    Color::Magenta => print!("{}", color::Bg(color::Magenta)),
-                    Color::Cyan => print!("{}", color::Bg(color::Cyan)),
-                    Color::White => print!("{}", color::Bg(color::White)),
-                };
-
-                match fore {
-                    Color::Black => print!("{}", color::Fg(color::Black)),
-                    Color::Red => print!("{}", color::Fg(color::Red)),
-                    Color::Green => print!("{}", color::Fg(color::Green)),
-                    Color::Yellow => print!("{}", color::Fg(color::Yellow)),
-                    Color::Blue => print!("{}", color::Fg(color::Blue)),
-                    Color::Magenta => print!("{}", color::Fg(color::Magenta)),
-                    Color::Cyan => print!("{}", color::Fg(color::Cyan)),
-                    Color::White => print!("{}", color::Fg(color::White)),
-                };
+                if self.truecolor {
+                    let [br, bg, bb] = back.to_bytes();
+                    let [fr, fg, fb] = fore.to_bytes();
+                    print!("{}{}",
+                           color::Bg(color::Rgb(br, bg, bb)),
+                           color::Fg(color::Rgb(fr, fg, fb)));
+                } else {
+                    print!("{}{}",
+                           color::Bg(color::AnsiValue(self.palette().nearest(back) as u8)),
+                           color::Fg(color::AnsiValue(self.palette().nearest(fore) as u8)));
+                }
                 print!("▄");
             }
             println!("{}", termion::style::Reset);
@@ -51,23 +57,32 @@ impl Driver for TermDriver {
 }
 
 fn main() {
-    use Color::*;
-    let scene = Scene
-        + Object::new(sphere_fn(v3(10, 5, 2), fp(3)), m(Material::Surface(Yellow, Red, Black)))
-        + Object::new(sphere_fn(v3(5, 10, 2), fp(3)), m(Material::Mirror))
-        + Object::new(sphere_fn(v3(0, 15, 2), fp(3)), m(Material::Surface(Yellow, Red, Black)))
-        + Object::new(plane_fn(v3(0, 0, 1), fp(0)), checkerboard(Material::Surface(Green, Green, Black), Material::Surface(White, White, Black)))
-        ;
+    use std::io::Read;
+
+    // Render the scene file given as argument or the default scene.
+    let mut input = Vec::new();
+    match std::env::args().nth(1) {
+        Some(path) => {
+            std::fs::File::open(path)
+                .and_then(|mut f| f.read_to_end(&mut input))
+                .expect("Couldn't read scene file");
+        }
+        None => input.extend_from_slice(include_bytes!("../scenes/balls.txt")),
+    }
 
-    let frustum = Frustum {
-        origin: v3(0, 0, 4),
-        dir: v3(8, 4, -1).normalized(),
-        up: v3(0, 0, 1),
+    let desc = match text::parse::<64>(&input) {
+        Ok(desc) => desc,
+        Err(e) => {
+            eprintln!("{}:{}: {:?}", e.line, e.column, e.kind);
+            std::process::exit(1);
+        }
     };
 
-    let light_dir = v3(1, 1, -4).normalized();
+    let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+    let driver = TermDriver { truecolor: colorterm == "truecolor" || colorterm == "24bit" };
 
-    TermDriver.draw_screen(|x, y| {
-        trace(&scene, frustum.ray(x, y), &light_dir)
+    let settings = TraceSettings::quality();
+    driver.draw_screen_rgb(|x, y| {
+        trace_rgb(&desc, desc.camera.ray(x, y), &desc.lights, &settings)
     });
 }
@@ -0,0 +1,37 @@
+//! Pack text scene files into a binary scene bank for flash.
+//!
+//! Usage: pack scene1.txt scene2.txt ... > scenes.bin
+
+extern crate fixray;
+
+use std::io::{Read, Write};
+use fixray::{binary, text};
+
+fn main() {
+    let mut bank = Vec::new();
+
+    for path in std::env::args().skip(1) {
+        let mut input = Vec::new();
+        std::fs::File::open(&path)
+            .and_then(|mut f| f.read_to_end(&mut input))
+            .expect("Couldn't read scene file");
+
+        let desc = match text::parse::<256>(&input) {
+            Ok(desc) => desc,
+            Err(e) => {
+                eprintln!("{}:{}:{}: {:?}", path, e.line, e.column, e.kind);
+                std::process::exit(1);
+            }
+        };
+
+        let mut buf = vec![0u8; 0x10000];
+        let mut enc = binary::Encoder::new(&mut buf);
+        let len = enc.scene(&desc)
+            .and_then(|_| enc.finish())
+            .expect("Scene too large to encode");
+        eprintln!("{}: {} bytes", path, len);
+        bank.extend_from_slice(&buf[..len]);
+    }
+
+    std::io::stdout().write_all(&bank).expect("Couldn't write output");
+}
@@ -0,0 +1,869 @@
+//! Compact binary scene encoding for storing scenes in flash.
+//!
+//! An encoded scene is an 8-byte header followed by a payload of records.
+//!
+//! ```text
+//! header   "FX" version:u8 0:u8 payload-length:u16 checksum:u16
+//! record   tag:u8 data...
+//! ```
+//!
+//! Multi-byte values are little-endian and fixed-point numbers are stored as their raw `i32`.
+//! The checksum is Fletcher-16 over the payload. Encoded scenes can be concatenated into a bank
+//! and picked by index with `nth_scene`.
+//!
+//! The records are
+//!
+//! ```text
+//! camera   1 origin:v3 dir:v3 up:v3
+//! light    2 light-kind:u8 light-params
+//! light-kind 0 dir:v3 intensity:fp
+//!            | 1 pos:v3 intensity:fp range:fp
+//!            | 2 pos:v3 dir:v3 angle:fp intensity:fp range:fp
+//! object   3 shape-kind:u8 shape-params:fp* material-id:u8 transform
+//! material 4 name-length:u8 name:u8* paint
+//! paint    0 material-kind | 1 material-kind material-kind
+//! material-kind 0 | 1 highlight:u8 color:u8 shadow:u8
+//!               | 2 albedo:u8 reflectivity:fp specular:fp emission:fp | 3 tint:u8
+//!               | 4 refraction-index:fp
+//! transform 0 | 1 axes:v3*3 offset:v3 scale:fp
+//! ```
+//!
+//! Shape kinds and their parameters follow the order of the `Shape` enum. Materials get their
+//! ids in the order of their records. Loading checks values the same way as the text format, and
+//! a scene without light records gets the light of `Description::add_default_light`.
+
+use fp::{fp, FP};
+use v::V3;
+use geom::{Frustum, Transform, MIN_SCALE, MAX_SCALE};
+use light::Light;
+use scene::{Description, Primitive};
+use material::{Finish, MaterialId, MAX_NAME};
+use rgb::Rgb;
+use shape::Shape;
+use {Color, Material, Paint};
+
+pub const VERSION: u8 = 1;
+
+const MAGIC: &[u8] = b"FX";
+const HEADER_LEN: usize = 8;
+
+const TAG_CAMERA: u8 = 1;
+const TAG_LIGHT: u8 = 2;
+const TAG_OBJECT: u8 = 3;
+const TAG_MATERIAL: u8 = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    /// Encoding ran out of buffer space.
+    BufferFull,
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadChecksum,
+    /// Data ended in the middle of a value.
+    Truncated,
+    /// Unknown tag or enum value, or the kind of a value with a parameter out of range.
+    BadValue(u8),
+    TooManyObjects,
+    /// Material table full or material name too long.
+    TooManyMaterials,
+    TooManyLights,
+}
+
+/// Validated encoded scene that decodes records straight from the borrowed bytes.
+#[derive(Copy, Clone)]
+pub struct Encoded<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> Encoded<'a> {
+    /// Check the header and the checksum of the scene at the start of `data`.
+    ///
+    /// Return the scene and the bytes after it.
+    pub fn new(data: &'a [u8]) -> Result<(Encoded<'a>, &'a [u8]), Error> {
+        if data.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        if &data[0..2] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if data[2] != VERSION {
+            return Err(Error::UnsupportedVersion(data[2]));
+        }
+        let len = (data[4] as usize) | (data[5] as usize) << 8;
+        let checksum = (data[6] as u16) | (data[7] as u16) << 8;
+        if data.len() < HEADER_LEN + len {
+            return Err(Error::Truncated);
+        }
+
+        let payload = &data[HEADER_LEN..HEADER_LEN + len];
+        if fletcher16(payload) != checksum {
+            return Err(Error::BadChecksum);
+        }
+        Ok((Encoded { payload }, &data[HEADER_LEN + len..]))
+    }
+
+    pub fn records(&self) -> Records<'a> {
+        Records {
+            reader: Reader {
+                data: self.payload,
+                pos: 0,
+            },
+        }
+    }
+
+    /// Decode the whole scene.
+    pub fn load<const N: usize>(&self) -> Result<Description<N>, Error> {
+        let mut desc: Description<N> = Description::new();
+        for r in self.records() {
+            match r? {
+                Record::Camera(camera) => desc.camera = camera,
+                Record::Light(light) => {
+                    if desc.lights.push(light).is_err() {
+                        return Err(Error::TooManyLights);
+                    }
+                }
+                Record::Object(object) => {
+                    if desc.objects.insert(object).is_err() {
+                        return Err(Error::TooManyObjects);
+                    }
+                }
+                Record::Material(name, paint) => {
+                    if desc.materials.insert(name, paint).is_none() {
+                        return Err(Error::TooManyMaterials);
+                    }
+                }
+            }
+        }
+        desc.add_default_light();
+        Ok(desc)
+    }
+}
+
+/// Pick the `n`th scene from a bank of concatenated scenes.
+pub fn nth_scene<'a>(bank: &'a [u8], n: usize) -> Result<Encoded<'a>, Error> {
+    let mut rest = bank;
+    for _ in 0..n {
+        rest = Encoded::new(rest)?.1;
+    }
+    Ok(Encoded::new(rest)?.0)
+}
+
+/// Number of valid scenes at the start of a bank.
+pub fn scene_count(bank: &[u8]) -> usize {
+    let mut rest = bank;
+    let mut n = 0;
+    while let Ok((_, next)) = Encoded::new(rest) {
+        rest = next;
+        n += 1;
+    }
+    n
+}
+
+pub enum Record<'a> {
+    Camera(Frustum),
+    Light(Light),
+    Object(Primitive),
+    /// Named material, borrowing the name from the encoded data.
+    Material(&'a [u8], Paint),
+}
+
+pub struct Records<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, Error>;
+
+    fn next(&mut self) -> Option<Result<Record<'a>, Error>> {
+        if self.reader.is_empty() {
+            return None;
+        }
+        let ret = self.reader.record();
+        if ret.is_err() {
+            // Stop at the first error, there's no telling where the next record would start.
+            self.reader.pos = self.reader.data.len();
+        }
+        Some(ret)
+    }
+}
+
+/// Writer for a single encoded scene.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Encoder<'a> {
+        Encoder {
+            buf,
+            pos: HEADER_LEN,
+        }
+    }
+
+    /// Encoder for bare values without a scene header, for other formats that embed them.
+    pub(crate) fn bare(buf: &'a mut [u8]) -> Encoder<'a> {
+        Encoder { buf, pos: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn camera(&mut self, camera: &Frustum) -> Result<(), Error> {
+        self.u8(TAG_CAMERA)?;
+        self.v3(&camera.origin)?;
+        self.v3(&camera.dir)?;
+        self.v3(&camera.up)
+    }
+
+    pub fn light(&mut self, light: &Light) -> Result<(), Error> {
+        self.u8(TAG_LIGHT)?;
+        match *light {
+            Light::Directional { dir, intensity } => {
+                self.u8(0)?;
+                self.v3(&dir)?;
+                self.fp(intensity)
+            }
+            Light::Point { pos, intensity, range } => {
+                self.u8(1)?;
+                self.v3(&pos)?;
+                self.fp(intensity)?;
+                self.fp(range)
+            }
+            Light::Spot { pos, dir, angle, intensity, range } => {
+                self.u8(2)?;
+                self.v3(&pos)?;
+                self.v3(&dir)?;
+                self.fp(angle)?;
+                self.fp(intensity)?;
+                self.fp(range)
+            }
+        }
+    }
+
+    pub fn object(&mut self, object: &Primitive) -> Result<(), Error> {
+        self.u8(TAG_OBJECT)?;
+        self.shape(&object.shape)?;
+        if object.material.0 > 0xff {
+            return Err(Error::TooManyMaterials);
+        }
+        self.u8(object.material.0 as u8)?;
+        self.transform(&object.transform)
+    }
+
+    /// Encode a named material, they are numbered in the order they are written.
+    pub fn material(&mut self, name: &[u8], paint: &Paint) -> Result<(), Error> {
+        if name.len() > MAX_NAME {
+            return Err(Error::TooManyMaterials);
+        }
+        self.u8(TAG_MATERIAL)?;
+        self.u8(name.len() as u8)?;
+        for &c in name {
+            self.u8(c)?;
+        }
+        match *paint {
+            Paint::Solid(m) => {
+                self.u8(0)?;
+                self.material_kind(&m)
+            }
+            Paint::Checkerboard(m1, m2) => {
+                self.u8(1)?;
+                self.material_kind(&m1)?;
+                self.material_kind(&m2)
+            }
+        }
+    }
+
+    /// Encode the materials and all the enabled objects of a scene along with its camera and
+    /// lights.
+    pub fn scene<const N: usize, const M: usize, const L: usize>(&mut self,
+                                                                 desc: &Description<N, M, L>)
+                                                                 -> Result<(), Error> {
+        self.camera(&desc.camera)?;
+        for light in desc.lights.iter() {
+            self.light(light)?;
+        }
+        for (_, name, paint) in desc.materials.iter() {
+            self.material(name, paint)?;
+        }
+        for (_, object) in desc.objects.iter() {
+            self.object(object)?;
+        }
+        Ok(())
+    }
+
+    /// Write the header and return the total length of the encoded scene.
+    pub fn finish(self) -> Result<usize, Error> {
+        let len = self.pos - HEADER_LEN;
+        if self.buf.len() < HEADER_LEN || len > 0xffff {
+            return Err(Error::BufferFull);
+        }
+        let checksum = fletcher16(&self.buf[HEADER_LEN..self.pos]);
+        let header = [MAGIC[0],
+                      MAGIC[1],
+                      VERSION,
+                      0,
+                      len as u8,
+                      (len >> 8) as u8,
+                      checksum as u8,
+                      (checksum >> 8) as u8];
+        self.buf[..HEADER_LEN].copy_from_slice(&header);
+        Ok(self.pos)
+    }
+
+    pub(crate) fn u8(&mut self, x: u8) -> Result<(), Error> {
+        if self.pos >= self.buf.len() {
+            return Err(Error::BufferFull);
+        }
+        self.buf[self.pos] = x;
+        self.pos += 1;
+        Ok(())
+    }
+
+    pub(crate) fn fp(&mut self, x: FP) -> Result<(), Error> {
+        for i in 0..4 {
+            self.u8((x.0 >> (i * 8)) as u8)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn v3(&mut self, v: &V3) -> Result<(), Error> {
+        self.fp(v.x)?;
+        self.fp(v.y)?;
+        self.fp(v.z)
+    }
+
+    fn material_kind(&mut self, m: &Material) -> Result<(), Error> {
+        match *m {
+            Material::Mirror => self.u8(0),
+            Material::Surface(highlight, col, shadow) => {
+                self.u8(1)?;
+                self.u8(highlight as u8)?;
+                self.u8(col as u8)?;
+                self.u8(shadow as u8)
+            }
+            Material::Finish(f) => {
+                self.u8(2)?;
+                // Only the 3-bit color closest to the albedo is stored.
+                self.u8(f.albedo.quantize() as u8)?;
+                self.fp(f.reflectivity)?;
+                self.fp(f.specular)?;
+                self.fp(f.emission)
+            }
+            Material::Tinted(tint) => {
+                self.u8(3)?;
+                self.u8(tint as u8)
+            }
+            Material::Glass(ior) => {
+                self.u8(4)?;
+                self.fp(ior)
+            }
+        }
+    }
+
+    fn transform(&mut self, t: &Transform) -> Result<(), Error> {
+        let identity = Transform::identity();
+        if t.axes == identity.axes && t.offset == identity.offset && t.scale == identity.scale {
+            return self.u8(0);
+        }
+        self.u8(1)?;
+        for axis in &t.axes {
+            self.v3(axis)?;
+        }
+        self.v3(&t.offset)?;
+        self.fp(t.scale)
+    }
+
+    pub(crate) fn shape(&mut self, shape: &Shape) -> Result<(), Error> {
+        match *shape {
+            Shape::Sphere { center, radius } => {
+                self.u8(0)?;
+                self.v3(&center)?;
+                self.fp(radius)
+            }
+            Shape::Plane { normal, offset } => {
+                self.u8(1)?;
+                self.v3(&normal)?;
+                self.fp(offset)
+            }
+            Shape::Box { center, half_size } => {
+                self.u8(2)?;
+                self.v3(&center)?;
+                self.v3(&half_size)
+            }
+            Shape::RoundedBox { center, half_size, radius } => {
+                self.u8(3)?;
+                self.v3(&center)?;
+                self.v3(&half_size)?;
+                self.fp(radius)
+            }
+            Shape::Torus { center, major_radius, minor_radius } => {
+                self.u8(4)?;
+                self.v3(&center)?;
+                self.fp(major_radius)?;
+                self.fp(minor_radius)
+            }
+            Shape::Segment { a, b } => {
+                self.u8(5)?;
+                self.v3(&a)?;
+                self.v3(&b)
+            }
+            Shape::Capsule { a, b, radius } => {
+                self.u8(6)?;
+                self.v3(&a)?;
+                self.v3(&b)?;
+                self.fp(radius)
+            }
+            Shape::Cylinder { center, radius, half_height } => {
+                self.u8(7)?;
+                self.v3(&center)?;
+                self.fp(radius)?;
+                self.fp(half_height)
+            }
+            Shape::Cone { base, radius, height } => {
+                self.u8(8)?;
+                self.v3(&base)?;
+                self.fp(radius)?;
+                self.fp(height)
+            }
+            Shape::HexPrism { center, apothem, half_height } => {
+                self.u8(9)?;
+                self.v3(&center)?;
+                self.fp(apothem)?;
+                self.fp(half_height)
+            }
+            Shape::Ellipsoid { center, radii } => {
+                self.u8(10)?;
+                self.v3(&center)?;
+                self.v3(&radii)
+            }
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+        if self.is_empty() {
+            return Err(Error::Truncated);
+        }
+        self.pos += 1;
+        Ok(self.data[self.pos - 1])
+    }
+
+    pub(crate) fn fp(&mut self) -> Result<FP, Error> {
+        let mut x = 0u32;
+        for i in 0..4 {
+            x |= (self.u8()? as u32) << (i * 8);
+        }
+        Ok(FP(x as i32))
+    }
+
+    pub(crate) fn v3(&mut self) -> Result<V3, Error> {
+        Ok(V3::new(self.fp()?, self.fp()?, self.fp()?))
+    }
+
+    /// Number that must be greater than zero in a value of `kind`.
+    fn positive(&mut self, kind: u8) -> Result<FP, Error> {
+        match self.fp()? {
+            x if x > FP(0) => Ok(x),
+            _ => Err(Error::BadValue(kind)),
+        }
+    }
+
+    /// Positive size in a value of `kind` that doesn't vanish when squared.
+    fn length(&mut self, kind: u8) -> Result<FP, Error> {
+        match self.positive(kind)? {
+            x if x * x > FP(0) => Ok(x),
+            _ => Err(Error::BadValue(kind)),
+        }
+    }
+
+    fn color(&mut self) -> Result<Color, Error> {
+        Ok(match self.u8()? {
+            0 => Color::Black,
+            1 => Color::Red,
+            2 => Color::Green,
+            3 => Color::Yellow,
+            4 => Color::Blue,
+            5 => Color::Magenta,
+            6 => Color::Cyan,
+            7 => Color::White,
+            x => return Err(Error::BadValue(x)),
+        })
+    }
+
+    fn material(&mut self) -> Result<Material, Error> {
+        match self.u8()? {
+            0 => Ok(Material::Mirror),
+            1 => Ok(Material::Surface(self.color()?, self.color()?, self.color()?)),
+            2 => {
+                Ok(Material::Finish(Finish {
+                    albedo: Rgb::from(self.color()?),
+                    reflectivity: self.fp()?,
+                    specular: self.fp()?,
+                    emission: self.fp()?,
+                }))
+            }
+            3 => Ok(Material::Tinted(self.color()?)),
+            4 => Ok(Material::Glass(self.positive(4)?)),
+            x => Err(Error::BadValue(x)),
+        }
+    }
+
+    fn transform(&mut self) -> Result<Transform, Error> {
+        match self.u8()? {
+            0 => Ok(Transform::identity()),
+            1 => {
+                Ok(Transform {
+                    axes: [self.v3()?, self.v3()?, self.v3()?],
+                    offset: self.v3()?,
+                    scale: match self.fp()? {
+                        s if s >= MIN_SCALE && s <= MAX_SCALE => s,
+                        _ => return Err(Error::BadValue(1)),
+                    },
+                })
+            }
+            x => Err(Error::BadValue(x)),
+        }
+    }
+
+    pub(crate) fn shape(&mut self) -> Result<Shape, Error> {
+        let kind = self.u8()?;
+        Ok(match kind {
+            0 => {
+                Shape::Sphere {
+                    center: self.v3()?,
+                    radius: self.fp()?,
+                }
+            }
+            1 => {
+                // Planes are stored with the unit normal the distance needs.
+                let normal = self.v3()?;
+                if (normal.dot(&normal) - fp(1)).abs() > fp(1) / fp(16) {
+                    return Err(Error::BadValue(kind));
+                }
+                Shape::Plane {
+                    normal,
+                    offset: self.fp()?,
+                }
+            }
+            2 => {
+                Shape::Box {
+                    center: self.v3()?,
+                    half_size: self.v3()?,
+                }
+            }
+            3 => {
+                Shape::RoundedBox {
+                    center: self.v3()?,
+                    half_size: self.v3()?,
+                    radius: self.fp()?,
+                }
+            }
+            4 => {
+                Shape::Torus {
+                    center: self.v3()?,
+                    major_radius: self.fp()?,
+                    minor_radius: self.fp()?,
+                }
+            }
+            5 => {
+                Shape::Segment {
+                    a: self.v3()?,
+                    b: self.v3()?,
+                }
+            }
+            6 => {
+                Shape::Capsule {
+                    a: self.v3()?,
+                    b: self.v3()?,
+                    radius: self.fp()?,
+                }
+            }
+            7 => {
+                Shape::Cylinder {
+                    center: self.v3()?,
+                    radius: self.fp()?,
+                    half_height: self.fp()?,
+                }
+            }
+            8 => {
+                let (base, radius, height) = (self.v3()?, self.fp()?, self.fp()?);
+                if radius * radius + height * height == fp(0) {
+                    return Err(Error::BadValue(kind));
+                }
+                Shape::Cone { base, radius, height }
+            }
+            9 => {
+                Shape::HexPrism {
+                    center: self.v3()?,
+                    apothem: self.fp()?,
+                    half_height: self.fp()?,
+                }
+            }
+            10 => {
+                Shape::Ellipsoid {
+                    center: self.v3()?,
+                    radii: V3::new(self.length(kind)?, self.length(kind)?, self.length(kind)?),
+                }
+            }
+            x => return Err(Error::BadValue(x)),
+        })
+    }
+
+    fn record(&mut self) -> Result<Record<'a>, Error> {
+        match self.u8()? {
+            TAG_CAMERA => {
+                let camera = Frustum::new(self.v3()?, self.v3()?, self.v3()?);
+                Ok(Record::Camera(camera.ok_or(Error::BadValue(TAG_CAMERA))?))
+            }
+            TAG_LIGHT => {
+                Ok(Record::Light(match self.u8()? {
+                    0 => {
+                        Light::Directional {
+                            dir: self.v3()?,
+                            intensity: self.fp()?,
+                        }
+                    }
+                    1 => {
+                        Light::Point {
+                            pos: self.v3()?,
+                            intensity: self.fp()?,
+                            range: self.fp()?,
+                        }
+                    }
+                    2 => {
+                        Light::Spot {
+                            pos: self.v3()?,
+                            dir: self.v3()?,
+                            angle: self.fp()?,
+                            intensity: self.fp()?,
+                            range: self.fp()?,
+                        }
+                    }
+                    x => return Err(Error::BadValue(x)),
+                }))
+            }
+            TAG_OBJECT => {
+                let shape = self.shape()?;
+                let material = MaterialId(self.u8()? as usize);
+                let transform = self.transform()?;
+                Ok(Record::Object(Primitive::new(shape, material).transformed(transform)))
+            }
+            TAG_MATERIAL => {
+                let len = self.u8()? as usize;
+                if self.data.len() - self.pos < len {
+                    return Err(Error::Truncated);
+                }
+                let name = &self.data[self.pos..self.pos + len];
+                self.pos += len;
+                let paint = match self.u8()? {
+                    0 => Paint::Solid(self.material()?),
+                    1 => Paint::Checkerboard(self.material()?, self.material()?),
+                    x => return Err(Error::BadValue(x)),
+                };
+                Ok(Record::Material(name, paint))
+            }
+            x => Err(Error::BadValue(x)),
+        }
+    }
+}
+
+fn fletcher16(data: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &x in data {
+        a = (a + x as u16) % 255;
+        b = (b + a) % 255;
+    }
+    b << 8 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::{fp, PI};
+    use v::v3;
+    use scene::Body;
+    use text;
+    use super::*;
+
+    const SCENE: &[u8] = b"
+        camera 0 0 4  8 4 -1  0 0 1
+        light 1 1 -4
+        spot_light 0 0 10  0 0 -1  30  1 20
+        material red surface yellow red black
+        material chrome mirror
+        material floor checkerboard red chrome
+        material glow finish blue 0 0.5 0.25
+        material gold tinted yellow
+        material lens glass 1.5
+        sphere red 10 5 2  3
+        rotate z 30
+        torus chrome 0 10 0  3 1
+        reset
+        plane floor 0 0 1  0
+    ";
+
+    #[test]
+    fn test_roundtrip() {
+        let desc = text::parse::<4>(SCENE).ok().unwrap();
+
+        let mut buf = [0u8; 1024];
+        let len = {
+            let mut enc = Encoder::new(&mut buf);
+            enc.scene(&desc).ok().unwrap();
+            enc.finish().ok().unwrap()
+        };
+
+        let (scene, rest) = Encoded::new(&buf[..len]).ok().unwrap();
+        assert!(rest.is_empty());
+        let loaded = scene.load::<4>().ok().unwrap();
+        assert!(loaded.objects.len() == 3);
+        assert!(loaded.camera.dir.x == desc.camera.dir.x);
+        assert!(loaded.lights.len() == 2);
+        match loaded.lights[1] {
+            Light::Spot { dir, angle, .. } => {
+                assert!(dir.z == fp(-1));
+                assert!(angle == PI / fp(6));
+            }
+            _ => panic!("Wrong light"),
+        }
+        for &p in &[v3(10, 5, 2), v3(0, 9, 0), v3(3, -2, 1), v3(-5, 8, 3)] {
+            assert!(loaded.objects.distance(&p) == desc.objects.distance(&p));
+        }
+        assert!(loaded.materials.len() == 6);
+        assert!(loaded.materials.find(b"floor") == desc.materials.find(b"floor"));
+        match loaded.materials.get(MaterialId(3)) {
+            Some(&Paint::Solid(Material::Finish(f))) => {
+                assert!(f.albedo == Rgb::from(Color::Blue));
+                assert!(f.emission == fp(1) / fp(4));
+            }
+            _ => panic!("Wrong material"),
+        }
+        match loaded.materials.get(MaterialId(4)) {
+            Some(&Paint::Solid(Material::Tinted(Color::Yellow))) => {}
+            _ => panic!("Wrong material"),
+        }
+        match loaded.materials.get(MaterialId(5)) {
+            Some(&Paint::Solid(Material::Glass(ior))) => assert!(ior == fp(3) / fp(2)),
+            _ => panic!("Wrong material"),
+        }
+
+        // Too many objects for the target.
+        assert!(scene.load::<2>().err() == Some(Error::TooManyObjects));
+
+        // Corruption is caught.
+        buf[20] ^= 1;
+        assert!(Encoded::new(&buf[..len]).err() == Some(Error::BadChecksum));
+        assert!(Encoded::new(&buf[..len - 1]).err() == Some(Error::Truncated));
+    }
+
+    #[test]
+    fn test_bad_values() {
+        fn load(write: &dyn Fn(&mut Encoder) -> Result<(), Error>) -> Option<Error> {
+            let mut buf = [0u8; 128];
+            let len = {
+                let mut enc = Encoder::new(&mut buf);
+                write(&mut enc).ok().unwrap();
+                enc.finish().ok().unwrap()
+            };
+            Encoded::new(&buf[..len]).ok().unwrap().0.load::<1>().err()
+        }
+        let shape = |shape, transform| {
+            move |enc: &mut Encoder| {
+                enc.object(&Primitive::new(shape, MaterialId(0)).transformed(transform))
+            }
+        };
+        let ball = Shape::Sphere { center: v3(0, 0, 0), radius: fp(1) };
+        let flat = Shape::Ellipsoid { center: v3(0, 0, 0), radii: v3(2, 0, 1) };
+        let thin = Shape::Ellipsoid { center: v3(0, 0, 0), radii: V3::new(FP(12), fp(1), fp(1)) };
+        let speck = Shape::Cone { base: v3(0, 0, 0), radius: FP(12), height: FP(12) };
+        let tilted = Shape::Plane { normal: v3(1, 1, 0), offset: fp(0) };
+        let id = Transform::identity();
+
+        assert!(load(&shape(ball, id)).is_none());
+        assert!(load(&shape(flat, id)) == Some(Error::BadValue(10)));
+        assert!(load(&shape(thin, id)) == Some(Error::BadValue(10)));
+        assert!(load(&shape(speck, id)) == Some(Error::BadValue(8)));
+        assert!(load(&shape(tilted, id)) == Some(Error::BadValue(1)));
+        for &scale in &[fp(0), FP(2), fp(20)] {
+            assert!(load(&shape(ball, id.scale(scale))) == Some(Error::BadValue(1)));
+        }
+
+        let glass = |enc: &mut Encoder| enc.material(b"g", &Paint::Solid(Material::Glass(fp(0))));
+        assert!(load(&glass) == Some(Error::BadValue(4)));
+
+        let camera = |dir, up| {
+            move |enc: &mut Encoder| {
+                enc.camera(&Frustum {
+                    origin: v3(0, 0, 0),
+                    dir,
+                    up,
+                })
+            }
+        };
+        assert!(load(&camera(v3(1, 0, 0), v3(0, 0, 1))).is_none());
+        assert!(load(&camera(v3(0, 0, 0), v3(0, 0, 1))) == Some(Error::BadValue(TAG_CAMERA)));
+        assert!(load(&camera(v3(0, 0, 1), v3(0, 0, -1))) == Some(Error::BadValue(TAG_CAMERA)));
+    }
+
+    #[test]
+    fn test_default_light() {
+        let mut buf = [0u8; 64];
+        let len = {
+            let mut enc = Encoder::new(&mut buf);
+            enc.object(&Primitive::new(Shape::Sphere { center: v3(0, 0, 0), radius: fp(1) },
+                                       MaterialId(0)))
+                .ok()
+                .unwrap();
+            enc.finish().ok().unwrap()
+        };
+        let desc = Encoded::new(&buf[..len]).ok().unwrap().0.load::<1>().ok().unwrap();
+        assert!(desc.lights.len() == 1);
+        match desc.lights[0] {
+            Light::Directional { dir, intensity } => {
+                assert!(dir == v3(0, 0, -1) && intensity == fp(1))
+            }
+            _ => panic!("Wrong light"),
+        }
+    }
+
+    #[test]
+    fn test_bank() {
+        let mut buf = [0u8; 256];
+        let mut len = 0;
+        for i in 0..3 {
+            let mut enc = Encoder::new(&mut buf[len..]);
+            enc.light(&Light::Directional {
+                     dir: v3(0, 0, -i),
+                     intensity: fp(1),
+                 })
+                .ok()
+                .unwrap();
+            len += enc.finish().ok().unwrap();
+        }
+        let bank = &buf[..len];
+
+        assert!(scene_count(bank) == 3);
+        let scene = nth_scene(bank, 2).ok().unwrap().load::<1>().ok().unwrap();
+        match scene.lights[0] {
+            Light::Directional { dir, .. } => assert!(dir.z == fp(-2)),
+            _ => panic!("Wrong light"),
+        }
+        assert!(nth_scene(bank, 3).err() == Some(Error::Truncated));
+
+        // Small buffers fail cleanly.
+        let mut small = [0u8; 10];
+        let mut enc = Encoder::new(&mut small);
+        assert!(enc.camera(&scene.camera).err() == Some(Error::BufferFull));
+    }
+}
@@ -0,0 +1,164 @@
+//! Dithering full color pixels down to the 3-bit palette.
+//!
+//! Ordered dithering looks at one pixel at a time, so it fits the `Driver` callback as is. Error
+//! diffusion spreads each pixel's rounding error to its neighbors and needs the pixels a row at
+//! a time, top to bottom.
+
+use core::cmp::min;
+
+use fp::{fp, FP};
+use rgb::Rgb;
+use Color;
+
+/// 4x4 Bayer threshold matrix.
+const BAYER: [[i32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Ordered dither of the pixel at `(x, y)`.
+pub fn bayer(rgb: Rgb, x: u32, y: u32) -> Color {
+    // Threshold in the middle of the matrix cell's sixteenth.
+    let threshold = FP((2 * BAYER[(y % 4) as usize][(x % 4) as usize] + 1) << 3);
+    (rgb + Rgb::gray(fp(1) / fp(2) - threshold)).quantize()
+}
+
+/// Turn a full color pixel function into an ordered dithered one for `Driver::draw_screen`.
+pub fn ordered<F>(pixel_f: F) -> impl Fn(u32, u32) -> Color
+    where F: Fn(u32, u32) -> Rgb
+{
+    move |x, y| bayer(pixel_f(x, y), x, y)
+}
+
+/// How error diffusion spreads the error of a pixel.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Kernel {
+    /// Floyd-Steinberg, passes on all of the error.
+    FloydSteinberg,
+    /// Atkinson, passes on three quarters of the error for more contrast.
+    Atkinson,
+}
+
+/// Error diffusion over rows of up to `W` pixels.
+///
+/// Feed the rows of a frame in order to `row`, the errors carried to the rows below are kept
+/// here between the calls.
+pub struct ErrorDiffusion<const W: usize> {
+    kernel: Kernel,
+    /// Errors for the next two rows.
+    below: [[Rgb; W]; 2],
+}
+
+impl<const W: usize> ErrorDiffusion<W> {
+    pub fn new(kernel: Kernel) -> ErrorDiffusion<W> {
+        ErrorDiffusion {
+            kernel,
+            below: [[Rgb::gray(fp(0)); W]; 2],
+        }
+    }
+
+    /// Forget the errors from the previous rows, for starting a new frame.
+    pub fn reset(&mut self) {
+        self.below = [[Rgb::gray(fp(0)); W]; 2];
+    }
+
+    /// Dither the next row of pixels into `out`.
+    pub fn row(&mut self, pixels: &[Rgb], out: &mut [Color]) {
+        let zero = Rgb::gray(fp(0));
+        let carried = self.below[0];
+        self.below = [self.below[1], [zero; W]];
+        // Errors for the next two pixels on this row.
+        let mut ahead = [zero; 2];
+
+        for x in 0..min(W, min(pixels.len(), out.len())) {
+            // Colors past what the palette can show would pile up error without bound.
+            let want = (pixels[x] + carried[x] + ahead[0]).clamp();
+            ahead = [ahead[1], zero];
+            let color = want.quantize();
+            out[x] = color;
+            let err = want - Rgb::from(color);
+
+            match self.kernel {
+                Kernel::FloydSteinberg => {
+                    ahead[0] = ahead[0] + err * (fp(7) / fp(16));
+                    self.spread(0, x, -1, err * (fp(3) / fp(16)));
+                    self.spread(0, x, 0, err * (fp(5) / fp(16)));
+                    self.spread(0, x, 1, err * (fp(1) / fp(16)));
+                }
+                Kernel::Atkinson => {
+                    let e = err * (fp(1) / fp(8));
+                    ahead[0] = ahead[0] + e;
+                    ahead[1] = ahead[1] + e;
+                    self.spread(0, x, -1, e);
+                    self.spread(0, x, 0, e);
+                    self.spread(0, x, 1, e);
+                    self.spread(1, x, 0, e);
+                }
+            }
+        }
+    }
+
+    fn spread(&mut self, row: usize, x: usize, dx: isize, err: Rgb) {
+        let x = x as isize + dx;
+        if x >= 0 && (x as usize) < W {
+            let slot = &mut self.below[row][x as usize];
+            *slot = *slot + err;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bayer() {
+        // A gray level of k/16 lights up k pixels of each 4x4 tile.
+        for k in 0..17 {
+            let gray = Rgb::gray(fp(k) / fp(16));
+            let mut lit = 0;
+            for y in 0..4 {
+                for x in 0..4 {
+                    if bayer(gray, x + 4, y + 8) == Color::White {
+                        lit += 1;
+                    }
+                }
+            }
+            assert!(lit == k);
+        }
+
+        let pixel = ordered(|_, _| Rgb::from(Color::Magenta));
+        assert!(pixel(3, 5) == Color::Magenta);
+    }
+
+    #[test]
+    fn test_error_diffusion() {
+        // Atkinson drops some of the error and loses the faint pixels.
+        for &(kernel, min_lit) in &[(Kernel::FloydSteinberg, 24), (Kernel::Atkinson, 8)] {
+            let mut dither: ErrorDiffusion<16> = ErrorDiffusion::new(kernel);
+            let mut out = [Color::Black; 16];
+
+            dither.row(&[Rgb::from(Color::Cyan); 16], &mut out);
+            assert!(out.iter().all(|&c| c == Color::Cyan));
+
+            // A quarter gray comes out as at most about a quarter of white pixels.
+            let mut lit = 0;
+            for _ in 0..8 {
+                dither.row(&[Rgb::gray(fp(1) / fp(4)); 16], &mut out);
+                assert!(out.iter().all(|&c| c == Color::White || c == Color::Black));
+                lit += out.iter().filter(|&&c| c == Color::White).count();
+            }
+            assert!((min_lit..=40).contains(&lit));
+
+            dither.reset();
+            dither.row(&[Rgb::gray(fp(0)); 16], &mut out);
+            assert!(out.iter().all(|&c| c == Color::Black));
+
+            // Overbright pixels don't leave error behind for the dark ones after them.
+            for _ in 0..64 {
+                dither.row(&[Rgb::gray(fp(1000)); 16], &mut out);
+            }
+            assert!(out.iter().all(|&c| c == Color::White));
+            dither.row(&[Rgb::gray(fp(0)); 16], &mut out);
+            dither.row(&[Rgb::gray(fp(0)); 16], &mut out);
+            assert!(out.iter().all(|&c| c == Color::Black));
+        }
+    }
+}
@@ -3,6 +3,10 @@ use core::ops::{Add, Sub, Mul, Div, Neg};
 pub const DECIMAL_BITS: usize = 8;
 pub const EPSILON: FP = FP(4);
 
+pub const PI: FP = FP(804);
+pub const HALF_PI: FP = FP(402);
+pub const TAU: FP = FP(1608);
+
 #[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub struct FP(pub i32);
 
@@ -17,8 +21,10 @@ impl FP {
         } else if self < FP(0) {
             panic!("Negative sqrt");
         }
-        // Crappy Newton's method.
-        let mut x = self / fp(2);
+        // Crappy Newton's method, starting from a power of two near the root so that small and
+        // large inputs both converge within the fixed iteration count.
+        let bits = 32 - self.0.leading_zeros() as usize + DECIMAL_BITS;
+        let mut x = FP(1 << (bits / 2));
         for _ in 0..10 {
             x = (x + self / x) / fp(2);
         }
@@ -36,6 +42,61 @@ impl FP {
             self
         }
     }
+
+    /// Sine with a polynomial approximation.
+    pub fn sin(self) -> FP {
+        // Reduce to [-pi/2, pi/2] where the series behaves.
+        let mut x = FP(self.0 % TAU.0);
+        if x > PI {
+            x = x - TAU;
+        } else if x < -PI {
+            x = x + TAU;
+        }
+        if x > HALF_PI {
+            x = PI - x;
+        } else if x < -HALF_PI {
+            x = -PI - x;
+        }
+
+        let x2 = x * x;
+        x * (fp(1) - x2 / fp(6) * (fp(1) - x2 / fp(20) * (fp(1) - x2 / fp(42))))
+    }
+
+    pub fn cos(self) -> FP {
+        (self + HALF_PI).sin()
+    }
+
+    /// Four-quadrant arctangent of `self / x`, in [-pi, pi].
+    pub fn atan2(self, x: FP) -> FP {
+        let (ax, ay) = (x.abs(), self.abs());
+        if ax == FP(0) && ay == FP(0) {
+            return FP(0);
+        }
+
+        // Approximate atan in the first octant and mirror it out.
+        let z = if ax >= ay { ay / ax } else { ax / ay };
+        let mut a = z * (PI / fp(4) + FP(70) * (fp(1) - z));
+        if ay > ax {
+            a = HALF_PI - a;
+        }
+        if x < FP(0) {
+            a = PI - a;
+        }
+        if self < FP(0) {
+            a = -a;
+        }
+        a
+    }
+
+    pub fn clamp(self, min: FP, max: FP) -> FP {
+        if self < min {
+            min
+        } else if self > max {
+            max
+        } else {
+            self
+        }
+    }
 }
 
 impl Add for FP {
@@ -76,12 +137,35 @@ impl Neg for FP {
 
 #[cfg(test)]
 mod tests {
-    use super::fp;
+    use super::{fp, FP, PI, HALF_PI};
 
     #[test]
     fn test_fp() {
         assert!(fp(1) + fp(1) == fp(2));
 
         assert!(fp(10000).sqrt().to_i32() == 100);
+        assert!(FP(1).sqrt() == FP(16));
+        assert!(fp(1000000).sqrt().to_i32() == 1000);
+    }
+
+    #[test]
+    fn test_trig() {
+        fn close(a: FP, b: FP) -> bool {
+            (a - b).abs() <= FP(2)
+        }
+
+        assert!(close(fp(0).sin(), fp(0)));
+        assert!(close(HALF_PI.sin(), fp(1)));
+        assert!(close(PI.cos(), fp(-1)));
+        assert!(close((-HALF_PI).sin(), fp(-1)));
+        assert!(close((PI * fp(3) / fp(4)).sin(), (PI / fp(4)).sin()));
+        assert!(close(fp(7).sin(), FP(168)));
+        assert!(close(fp(-7).cos(), FP(193)));
+
+        assert!(close(fp(1).atan2(fp(1)), PI / fp(4)));
+        assert!(close(fp(1).atan2(fp(0)), HALF_PI));
+        assert!(close(fp(0).atan2(fp(-1)), PI));
+        assert!(close(fp(-1).atan2(fp(-1)), -PI * fp(3) / fp(4)));
+        assert!(close(fp(1).atan2(fp(2)), FP(119)));
     }
 }
@@ -1,5 +1,11 @@
-use fp::{fp, FP};
-use v::V3;
+use fp::{fp, FP, DECIMAL_BITS};
+use v::{v3, V3};
+
+/// Smallest overall scale of a `Transform`, smaller bodies have too little fixed-point precision
+/// and map world positions out of range in their local space.
+pub const MIN_SCALE: FP = FP(16);
+/// Largest overall scale of a `Transform`.
+pub const MAX_SCALE: FP = fp(16);
 
 pub struct Frustum {
     pub origin: V3,
@@ -8,6 +14,21 @@ pub struct Frustum {
 }
 
 impl Frustum {
+    /// Camera at `origin` looking along `dir` with `up` toward the top of the screen.
+    ///
+    /// Return `None` if a direction is zero or the two are within about 15 degrees of each other,
+    /// too close to tell the sides of the screen apart.
+    pub fn new(origin: V3, dir: V3, up: V3) -> Option<Frustum> {
+        if dir.dot(&dir) == fp(0) || up.dot(&up) == fp(0) {
+            return None;
+        }
+        let right = dir.normalized().cross(&up.normalized());
+        if right.dot(&right) < fp(1) / fp(16) {
+            return None;
+        }
+        Some(Frustum { origin, dir, up })
+    }
+
     // XXX: A bunch of hardcoding
     pub fn ray(&self, screen_x: u32, screen_y: u32) -> Ray {
         let x = (fp(screen_x as i32) - fp(32)) / fp(16);
@@ -19,7 +40,7 @@ impl Frustum {
 
         Ray {
             origin: self.origin,
-            dir: dir,
+            dir,
         }
     }
 }
@@ -31,16 +52,103 @@ pub struct Ray {
     pub dir: V3,
 }
 
+/// Placement of a body in the world.
+///
+/// Built up by chaining operations that each move the body further in world space. Bodies use
+/// the transform in reverse, to map world positions into their local space.
+#[derive(Copy, Clone)]
+pub struct Transform {
+    /// World space directions of the local axes.
+    pub axes: [V3; 3],
+    pub offset: V3,
+    pub scale: FP,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            axes: [v3(1, 0, 0), v3(0, 1, 0), v3(0, 0, 1)],
+            offset: v3(0, 0, 0),
+            scale: fp(1),
+        }
+    }
+
+    pub fn translate(self, offset: V3) -> Transform {
+        Transform { offset: self.offset + offset, ..self }
+    }
+
+    /// Uniform scaling around the origin.
+    pub fn scale(self, scale: FP) -> Transform {
+        Transform {
+            offset: self.offset * scale,
+            scale: self.scale * scale,
+            ..self
+        }
+    }
+
+    /// Scaling that keeps the overall scale between `MIN_SCALE` and `MAX_SCALE`.
+    pub fn checked_scale(self, scale: FP) -> Option<Transform> {
+        let total = (self.scale.0 as i64 * scale.0 as i64) >> DECIMAL_BITS;
+        if total < MIN_SCALE.0 as i64 || total > MAX_SCALE.0 as i64 {
+            return None;
+        }
+        Some(self.scale(scale))
+    }
+
+    /// Rotate around the x axis by an angle in radians.
+    pub fn rotate_x(self, angle: FP) -> Transform {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        self.rotate(|v| V3::new(v.x, v.y * cos - v.z * sin, v.y * sin + v.z * cos))
+    }
+
+    /// Rotate around the y axis by an angle in radians.
+    pub fn rotate_y(self, angle: FP) -> Transform {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        self.rotate(|v| V3::new(v.x * cos + v.z * sin, v.y, -v.x * sin + v.z * cos))
+    }
+
+    /// Rotate around the z axis by an angle in radians.
+    pub fn rotate_z(self, angle: FP) -> Transform {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        self.rotate(|v| V3::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos, v.z))
+    }
+
+    fn rotate<F: Fn(&V3) -> V3>(self, f: F) -> Transform {
+        Transform {
+            axes: [f(&self.axes[0]), f(&self.axes[1]), f(&self.axes[2])],
+            offset: f(&self.offset),
+            scale: self.scale,
+        }
+    }
+
+    /// Map a world space position to local space.
+    pub fn to_local(&self, pos: &V3) -> V3 {
+        let p = *pos - self.offset;
+        V3::new(self.axes[0].dot(&p) / self.scale,
+                self.axes[1].dot(&p) / self.scale,
+                self.axes[2].dot(&p) / self.scale)
+    }
+
+    /// Map a local space distance to world space.
+    pub fn to_world_distance(&self, distance: FP) -> FP {
+        distance * self.scale
+    }
+}
+
+// Analytic intersections from before the distance field tracer.
+#[allow(dead_code)]
 #[derive(Copy, Clone)]
 pub struct Intersection {
     pub distance: FP,
     pub normal: V3,
 }
 
+#[allow(dead_code)]
 pub trait Body {
     fn intersection(&self, ray: &Ray) -> Option<Intersection>;
 }
 
+#[allow(dead_code)]
 #[derive(Copy, Clone)]
 pub struct Sphere {
     pub center: V3,
@@ -74,11 +182,12 @@ impl Body for Sphere {
 
         Some(Intersection {
             distance: p,
-            normal: normal,
+            normal,
         })
     }
 }
 
+#[allow(dead_code)]
 #[derive(Copy, Clone)]
 pub struct Plane {
     pub normal: V3,
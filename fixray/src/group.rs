@@ -0,0 +1,254 @@
+//! Fixed-capacity collection of bodies that can be changed at runtime.
+
+use core::array;
+
+use fp::FP;
+use v::V3;
+use {Color, Material};
+use scene::{Body, ObjectId, SurfaceContext};
+
+/// Scene container with room for `N` bodies.
+///
+/// Every body sits in a numbered slot that stays the same until it is removed. Use
+/// `Group<&dyn Body, N>` to mix different body types or `Group<Primitive, N>` to own the
+/// objects. Disabled bodies stay in their slots but are not seen by the tracer.
+pub struct Group<T, const N: usize> {
+    slots: [Option<T>; N],
+    enabled: [bool; N],
+}
+
+impl<T: Body, const N: usize> Group<T, N> {
+    pub fn new() -> Group<T, N> {
+        Group {
+            slots: array::from_fn(|_| None),
+            enabled: [false; N],
+        }
+    }
+
+    /// Add a body to the first free slot and enable it.
+    ///
+    /// Return the slot index, or give the body back if the group is full.
+    pub fn insert(&mut self, body: T) -> Result<usize, T> {
+        match self.slots.iter().position(|s| s.is_none()) {
+            Some(i) => {
+                self.slots[i] = Some(body);
+                self.enabled[i] = true;
+                Ok(i)
+            }
+            None => Err(body),
+        }
+    }
+
+    /// Take the body out of a slot.
+    pub fn remove(&mut self, idx: usize) -> Option<T> {
+        let body = self.slots.get_mut(idx)?.take();
+        self.enabled[idx] = false;
+        body
+    }
+
+    /// Exchange the contents of two slots, or do nothing if either is out of range.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if a < N && b < N {
+            self.slots.swap(a, b);
+            self.enabled.swap(a, b);
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.slots.get(idx)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.slots.get_mut(idx)?.as_mut()
+    }
+
+    /// Show or hide the body in a slot, empty and out of range slots stay disabled.
+    pub fn set_enabled(&mut self, idx: usize, enabled: bool) {
+        if let Some(slot) = self.slots.get(idx) {
+            self.enabled[idx] = enabled && slot.is_some();
+        }
+    }
+
+    pub fn is_enabled(&self, idx: usize) -> bool {
+        self.enabled.get(idx) == Some(&true)
+    }
+
+    /// Number of occupied slots.
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Enabled bodies with their slot indices.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| self.enabled[i])
+            .filter_map(|(i, s)| s.as_ref().map(|b| (i, b)))
+    }
+
+    /// Enabled bodies with the id of their first leaf.
+    ///
+    /// Leaves are numbered over all occupied slots, so disabling a body does not renumber the
+    /// others.
+    fn leaves(&self) -> impl Iterator<Item = (usize, &T)> {
+        let mut first = 0;
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().map(|b| (i, b)))
+            .map(move |(i, b)| {
+                let id = first;
+                first += b.leaf_count();
+                (i, id, b)
+            })
+            .filter(move |&(i, _, _)| self.enabled[i])
+            .map(|(_, id, b)| (id, b))
+    }
+
+    /// Body containing a leaf and the leaf id within that body.
+    pub(crate) fn leaf(&self, id: ObjectId) -> Option<(&T, ObjectId)> {
+        self.leaves()
+            .find(|&(first, b)| id.0 >= first && id.0 < first + b.leaf_count())
+            .map(|(first, b)| (b, ObjectId(id.0 - first)))
+    }
+}
+
+impl<T: Body, const N: usize> Default for Group<T, N> {
+    fn default() -> Group<T, N> {
+        Group::new()
+    }
+}
+
+impl<T: Body, const N: usize> Body for Group<T, N> {
+    fn distance(&self, pos: &V3) -> FP {
+        self.iter()
+            .map(|(_, b)| b.distance(pos))
+            .min()
+            .unwrap_or(FP(i32::MAX))
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        let (_, id) = self.query(&ctx.pos);
+        self.leaf_material(id, ctx)
+    }
+
+    fn normal(&self, pos: &V3) -> V3 {
+        let (_, id) = self.query(pos);
+        self.leaf_normal(id, pos)
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.slots.iter().filter_map(|s| s.as_ref()).map(|b| b.leaf_count()).sum()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        self.leaves()
+            .map(|(first, b)| {
+                let (d, id) = b.query(pos);
+                (d, ObjectId(first + id.0))
+            })
+            .min_by_key(|&(d, _)| d)
+            .unwrap_or((FP(i32::MAX), ObjectId(0)))
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        match self.leaf(id) {
+            Some((b, id)) => b.leaf_material(id, ctx),
+            None => Material::Surface(Color::Cyan, Color::Cyan, Color::Cyan),
+        }
+    }
+
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        match self.leaf(id) {
+            Some((b, id)) => b.leaf_normal(id, pos),
+            None => pos.grad(|p| self.distance(&p)),
+        }
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        match self.leaf(id) {
+            Some((b, id)) => b.leaf_uv(id, pos),
+            None => (pos.x, pos.y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::fp;
+    use v::v3;
+    use scene::{Body, Primitive};
+    use shape::Shape;
+    use material::MaterialId;
+    use super::*;
+
+    fn ball(x: i32) -> Primitive {
+        Primitive::new(Shape::Sphere {
+                           center: v3(x, 0, 0),
+                           radius: fp(1),
+                       },
+                       MaterialId(0))
+    }
+
+    #[test]
+    fn test_group() {
+        let mut g: Group<Primitive, 2> = Group::new();
+        assert!(g.is_empty());
+        assert!(g.distance(&v3(0, 0, 0)) > fp(1000));
+
+        assert!(g.insert(ball(0)).ok() == Some(0));
+        assert!(g.insert(ball(10)).ok() == Some(1));
+        assert!(g.insert(ball(20)).is_err());
+        assert!(g.len() == 2);
+        assert!(g.distance(&v3(8, 0, 0)) == fp(1));
+
+        g.set_enabled(1, false);
+        assert!(g.distance(&v3(8, 0, 0)) == fp(7));
+        g.set_enabled(1, true);
+
+        assert!(g.remove(0).is_some());
+        assert!(g.distance(&v3(0, 0, 0)) == fp(9));
+        assert!(g.insert(ball(4)).ok() == Some(0));
+        assert!(g.distance(&v3(0, 0, 0)) == fp(3));
+
+        // Slots past the capacity are empty.
+        assert!(g.get(5).is_none() && g.get_mut(5).is_none() && g.remove(5).is_none());
+        g.set_enabled(5, true);
+        assert!(!g.is_enabled(5));
+        g.swap(0, 5);
+        assert!(g.is_enabled(0) && g.distance(&v3(0, 0, 0)) == fp(3));
+    }
+
+    #[test]
+    fn test_dyn_group() {
+        let a = ball(0);
+        let b = ball(10);
+        let mut g: Group<&dyn Body, 4> = Group::new();
+        g.insert(&a).ok();
+        g.insert(&b).ok();
+        assert!(g.distance(&v3(8, 0, 0)) == fp(1));
+    }
+
+    #[test]
+    fn test_group_query() {
+        let mut g: Group<Primitive, 4> = Group::new();
+        g.insert(ball(0)).ok();
+        g.insert(ball(10)).ok();
+        g.insert(ball(20)).ok();
+        assert!(g.leaf_count() == 3);
+        assert!(g.query(&v3(12, 0, 0)) == (fp(1), ObjectId(1)));
+
+        // Disabled bodies keep their leaf ids.
+        g.set_enabled(0, false);
+        assert!(g.query(&v3(19, 0, 0)) == (fp(0), ObjectId(2)));
+    }
+}
@@ -0,0 +1,237 @@
+//! Images painted on surfaces by their `uv` coordinates.
+//!
+//! The pixels are borrowed, so an image can stay in flash as a byte array or be read from a PPM
+//! file into a buffer on the host.
+
+use fp::{fp, FP};
+use material::Finish;
+use rgb::Rgb;
+use scene::SurfaceContext;
+use v::V3;
+use Material;
+
+/// How pixels are picked between pixel centers.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Filter {
+    /// The pixel the coordinates fall in, blocky up close.
+    Nearest,
+    /// Blend of the four closest pixels.
+    Bilinear,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    /// Not a binary PPM file.
+    NotPpm,
+    BadHeader,
+    /// Maximum channel value other than 255.
+    UnsupportedDepth,
+    /// Fewer pixels than the header says.
+    Truncated,
+}
+
+/// Image of 8 bit RGB pixels, stored row by row from the top.
+///
+/// The image covers the coordinates from 0 to 1 with `v` going up, and repeats outside of them.
+#[derive(Copy, Clone)]
+pub struct ImageTexture<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a [u8],
+    pub filter: Filter,
+}
+
+impl<'a> ImageTexture<'a> {
+    /// Image from 3 bytes per pixel.
+    ///
+    /// Return `None` if the image is empty or there are fewer than `width * height` pixels.
+    pub fn new(width: usize,
+               height: usize,
+               pixels: &'a [u8],
+               filter: Filter)
+               -> Option<ImageTexture<'a>> {
+        let len = width.checked_mul(height).and_then(|n| n.checked_mul(3));
+        match len {
+            Some(len) if len > 0 && len <= pixels.len() => {
+                Some(ImageTexture {
+                    width,
+                    height,
+                    pixels: &pixels[..len],
+                    filter,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Image from the contents of a binary PPM file, without copying the pixels.
+    pub fn from_ppm(data: &'a [u8], filter: Filter) -> Result<ImageTexture<'a>, Error> {
+        if !data.starts_with(b"P6") {
+            return Err(Error::NotPpm);
+        }
+        let mut pos = 2;
+        // Width, height and maximum value.
+        let mut fields = [0usize; 3];
+        for field in fields.iter_mut() {
+            pos = skip_space(data, pos);
+            let start = pos;
+            while pos < data.len() && data[pos].is_ascii_digit() {
+                *field = field.saturating_mul(10).saturating_add((data[pos] - b'0') as usize);
+                pos += 1;
+            }
+            if pos == start {
+                return Err(Error::BadHeader);
+            }
+        }
+        // A single whitespace separates the header from the pixels.
+        if pos == data.len() || !data[pos].is_ascii_whitespace() || fields[0] == 0 ||
+           fields[1] == 0 {
+            return Err(Error::BadHeader);
+        }
+        if fields[2] != 255 {
+            return Err(Error::UnsupportedDepth);
+        }
+        ImageTexture::new(fields[0], fields[1], &data[pos + 1..], filter).ok_or(Error::Truncated)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Pixel at column `x` of row `y`, counted from the top left.
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb {
+        let i = (y * self.width + x) * 3;
+        Rgb::from_bytes([self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]])
+    }
+
+    /// Pixel with the coordinates wrapped around the image.
+    fn wrapped(&self, x: i32, y: i32) -> Rgb {
+        self.pixel(x.rem_euclid(self.width as i32) as usize,
+                   y.rem_euclid(self.height as i32) as usize)
+    }
+
+    /// Color at surface coordinates.
+    pub fn sample(&self, uv: (FP, FP)) -> Rgb {
+        let x = uv.0 * fp(self.width as i32);
+        let y = (fp(1) - uv.1) * fp(self.height as i32);
+        match self.filter {
+            Filter::Nearest => self.wrapped(x.to_i32(), y.to_i32()),
+            Filter::Bilinear => {
+                // Blend between the centers of the pixels around.
+                let half = fp(1) / fp(2);
+                let (x, y) = (x - half, y - half);
+                let (x0, y0) = (x.to_i32(), y.to_i32());
+                let (tx, ty) = (x - fp(x0), y - fp(y0));
+                let top = self.wrapped(x0, y0).mix(self.wrapped(x0 + 1, y0), tx);
+                let bottom = self.wrapped(x0, y0 + 1).mix(self.wrapped(x0 + 1, y0 + 1), tx);
+                top.mix(bottom, ty)
+            }
+        }
+    }
+
+    /// Brightness at surface coordinates, for height maps.
+    pub fn luminance(&self, uv: (FP, FP)) -> FP {
+        self.sample(uv).luminance()
+    }
+
+    /// Height function for `Bump` from the brightness of the image, laid on the surface by
+    /// `uv_f`.
+    pub fn height_map<U>(&self, uv_f: U) -> impl Fn(&V3) -> FP + 'a
+        where U: Fn(&V3) -> (FP, FP) + 'a
+    {
+        let image = *self;
+        move |pos| image.luminance(uv_f(pos))
+    }
+
+    /// Material function for `Object::new` painting the image on `finish`.
+    pub fn material(&self, finish: Finish) -> impl Fn(&SurfaceContext) -> Material + 'a {
+        let image = *self;
+        move |ctx| Material::Finish(Finish { albedo: image.sample(ctx.uv), ..finish })
+    }
+}
+
+/// Skip whitespace and comments in a PPM header.
+fn skip_space(data: &[u8], mut pos: usize) -> usize {
+    while pos < data.len() {
+        if data[pos] == b'#' {
+            while pos < data.len() && data[pos] != b'\n' {
+                pos += 1;
+            }
+        } else if data[pos].is_ascii_whitespace() {
+            pos += 1;
+        } else {
+            break;
+        }
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use v::v3;
+    use {Color, Material};
+    use super::*;
+
+    /// Red and green over blue and white.
+    const PPM: &[u8] = b"P6\n# test\n2 2\n255\n\xff\x00\x00\x00\xff\x00\x00\x00\xff\xff\xff\xff";
+
+    #[test]
+    fn test_ppm() {
+        let image = ImageTexture::from_ppm(PPM, Filter::Nearest).unwrap();
+        assert!(image.width() == 2 && image.height() == 2);
+        assert!(image.pixel(1, 0) == Rgb::from(Color::Green));
+        assert!(image.pixel(0, 1) == Rgb::from(Color::Blue));
+
+        assert!(ImageTexture::from_ppm(b"P3\n2 2\n255\n", Filter::Nearest).err() ==
+                Some(Error::NotPpm));
+        assert!(ImageTexture::from_ppm(b"P6\n2\n", Filter::Nearest).err() ==
+                Some(Error::BadHeader));
+        assert!(ImageTexture::from_ppm(b"P6 2 2 15\n", Filter::Nearest).err() ==
+                Some(Error::UnsupportedDepth));
+        assert!(ImageTexture::from_ppm(&PPM[..PPM.len() - 1], Filter::Nearest).err() ==
+                Some(Error::Truncated));
+        assert!(ImageTexture::new(0, 4, &[], Filter::Nearest).is_none());
+    }
+
+    #[test]
+    fn test_sample() {
+        let quarter = fp(1) / fp(4);
+        let half = fp(1) / fp(2);
+        let red = Rgb::from(Color::Red);
+        let mut image = ImageTexture::from_ppm(PPM, Filter::Nearest).unwrap();
+        assert!(image.sample((quarter, fp(3) / fp(4))) == red);
+        assert!(image.sample((fp(3) / fp(4), quarter)) == Rgb::from(Color::White));
+        // The image repeats.
+        assert!(image.sample((fp(5) / fp(4), fp(-3) / fp(4))) == Rgb::from(Color::Blue));
+
+        image.filter = Filter::Bilinear;
+        // Exact at the pixel centers and blended between them.
+        assert!(image.sample((quarter, fp(3) / fp(4))) == red);
+        assert!(image.sample((half, fp(3) / fp(4))) == Rgb::from(Color::Yellow) * half);
+        assert!(image.sample((half, half)) == Rgb::gray(half));
+        assert!(image.luminance((fp(3) / fp(4), quarter)) == fp(1));
+
+        let paint = image.material(Finish::matte(Color::Black));
+        let ctx = SurfaceContext {
+            uv: (quarter, fp(3) / fp(4)),
+            ..SurfaceContext::at(v3(0, 0, 0))
+        };
+        match paint(&ctx) {
+            Material::Finish(f) => assert!(f.albedo == red),
+            _ => panic!("Wrong material"),
+        }
+        // Blends between pixels are kept for shading instead of being rounded to a 3-bit color.
+        let ctx = SurfaceContext { uv: (half, fp(3) / fp(4)), ..ctx };
+        match paint(&ctx) {
+            Material::Finish(f) => {
+                assert!(f.albedo == Rgb::from(Color::Yellow) * half);
+                assert!(f.shade(fp(1)) == Rgb::from(Color::Yellow) * half);
+            }
+            _ => panic!("Wrong material"),
+        }
+    }
+}
@@ -1,19 +1,45 @@
 #![no_std]
-#![feature(const_fn)]
-#![feature(conservative_impl_trait)]
 
+pub mod binary;
+pub mod dither;
 mod fp;
 mod geom;
+mod group;
+pub mod image;
+mod light;
+mod march;
+mod material;
+pub mod noise;
+mod ops;
+pub mod palette;
+mod rgb;
 mod scene;
+mod shape;
+pub mod text;
+pub mod texture;
+pub mod uv;
 mod v;
+pub mod vm;
+
+use core::cmp::{max, min};
 
 pub use fp::{fp, FP};
 pub use v::{v3, V3};
-pub use geom::{Ray, Frustum};
+pub use geom::{Ray, Frustum, Transform, MIN_SCALE, MAX_SCALE};
 
-pub use scene::{Scene, Body, Union, Object, sphere_fn, plane_fn};
+pub use scene::{Scene, Body, Union, Object, ObjectId, Primitive, Description, SurfaceContext};
+pub use group::Group;
+pub use light::{Light, Lights, MAX_LIGHTS};
+pub use march::{march, MarchSettings, Marched};
+pub use material::{MaterialId, MaterialTable, Finish, MAX_MATERIALS};
+pub use ops::{Repeat, Mirror, Polar, Displace, Bump};
+pub use rgb::Rgb;
+pub use shape::{Shape, sphere_fn, plane_fn, box_fn, rounded_box_fn, torus_fn, segment_fn, capsule_fn,
+                cylinder_fn, cone_fn, hex_prism_fn, ellipsoid_fn};
 
 /// 3-bit color.
+///
+/// The discriminants are the ANSI color numbers, which are also red, green and blue bitmasks.
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum Color {
     Black,
@@ -26,93 +52,995 @@ pub enum Color {
     White,
 }
 
+impl Color {
+    /// Color from red, green and blue bits in the lowest three bits.
+    pub fn from_bits(bits: u8) -> Color {
+        match bits & 7 {
+            0 => Color::Black,
+            1 => Color::Red,
+            2 => Color::Green,
+            3 => Color::Yellow,
+            4 => Color::Blue,
+            5 => Color::Magenta,
+            6 => Color::Cyan,
+            _ => Color::White,
+        }
+    }
+
+    /// Blend with `amount` of another color.
+    ///
+    /// Each channel goes to the side with more weight, an even mix keeps the channels of both.
+    pub fn mix(self, other: Color, amount: FP) -> Color {
+        let half = fp(1) / fp(2);
+        let a = if amount <= half { self as u8 } else { 0 };
+        let b = if amount >= half { other as u8 } else { 0 };
+        Color::from_bits(a | b)
+    }
+
+    /// Color seen through a filter of color `tint`.
+    pub fn tint(self, tint: Color) -> Color {
+        Color::from_bits(self as u8 & tint as u8)
+    }
+}
+
 /// 3-bit display driver.
 pub trait Driver {
+    /// Colors the display can show.
+    type Palette: palette::Palette;
+
+    fn palette(&self) -> &Self::Palette;
     fn screen_size(&self) -> (u32, u32);
     fn draw_screen<F>(&self, pixel_f: F) where F: Fn(u32, u32) -> Color;
+
+    /// Draw full color pixels, displays that can show more than the 3-bit palette override this.
+    fn draw_screen_rgb<F>(&self, pixel_f: F)
+        where F: Fn(u32, u32) -> Rgb
+    {
+        self.draw_screen(|x, y| pixel_f(x, y).quantize())
+    }
 }
 
 #[derive(Copy, Clone)]
 pub enum Material {
     // Perfect reflection.
     Mirror,
+    // Reflection seen through a colored filter.
+    Tinted(Color),
+    // Clear glass with this index of refraction, which has to be positive.
+    Glass(FP),
     // Highlight, material, shadow
     Surface(Color, Color, Color),
+    Finish(Finish),
 }
 
-pub fn checkerboard(m1: Material, m2: Material) -> impl Fn(&V3) -> Material {
-    // XXX: Arbitrary scale here, could do that with another fn?
-    move |&p| {
-        if ((p.x / fp(8)).to_i32() + (p.y / fp(8)).to_i32() +
-            (p.z / fp(8)).to_i32()) % 2 == 0 {
-            m1
-        } else {
-            m2
+/// Material function as plain data, for scenes built at runtime.
+#[derive(Copy, Clone)]
+pub enum Paint {
+    Solid(Material),
+    Checkerboard(Material, Material),
+}
+
+impl Paint {
+    pub fn material(&self, ctx: &SurfaceContext) -> Material {
+        match *self {
+            Paint::Solid(m) => m,
+            Paint::Checkerboard(m1, m2) => checkers(m1, m2)(&ctx.pos),
         }
     }
 }
 
-pub fn trace<T: Body>(body: &T, mut ray: Ray, light_dir: &V3) -> Color {
-    const MAX_STEPS: usize = 256;
-    const ESCAPE_VELOCITY: FP = fp(1000);
-    const SURFACE_DEFLECT: FP = FP(8);
+/// Checkers of 8 units, see `texture` for other sizes and patterns.
+pub fn checkerboard(m1: Material, m2: Material) -> impl Fn(&SurfaceContext) -> Material {
+    at_pos(checkers(m1, m2))
+}
 
-    let mut advance = fp(0);
-    let mut n_steps = 0;
+fn checkers(m1: Material, m2: Material) -> impl Fn(&V3) -> Material {
+    texture::select(texture::checker3(texture::Mapping::scale(fp(8))), m1, m2)
+}
 
-    let mut is_mirror = false;
+/// Material function that only looks at the position, for `Object::new`.
+pub fn at_pos<F>(material_f: F) -> impl Fn(&SurfaceContext) -> Material
+    where F: Fn(&V3) -> Material
+{
+    move |ctx| material_f(&ctx.pos)
+}
 
-    loop {
-        let pos = ray.origin + ray.dir * advance;
-        let d = body.distance(&pos);
+/// Settings for the ambient occlusion estimate.
+///
+/// The estimate samples the distance field at `steps` points spaced `spacing` apart along the
+/// normal, and a surface darkens by `strength` times how much closer the other surfaces are than
+/// the samples. Surfaces that end up less than half open are shaded like they were in shadow.
+#[derive(Copy, Clone)]
+pub struct AmbientOcclusion {
+    pub steps: usize,
+    pub spacing: FP,
+    pub strength: FP,
+}
 
-        if d <= FP(0) {
-            let normal = body.normal(&pos);
-
-            // TODO: Shadow when light source is blocked, needs a second type of trace function
-            // that just checks for path.
-            match body.material(&pos) {
-                Material::Mirror => {
-                    let reflect = (ray.dir - normal * (fp(2) * ray.dir.dot(&normal))).normalized();
-                    // Deflect a bit off the surface so we don't get stuck inside it...
-                    let pos = pos + normal * SURFACE_DEFLECT;
-                    ray = Ray { origin: pos, dir: reflect };
-                    is_mirror = true;
+impl AmbientOcclusion {
+    /// No ambient occlusion.
+    pub fn none() -> AmbientOcclusion {
+        AmbientOcclusion {
+            steps: 0,
+            spacing: fp(0),
+            strength: fp(0),
+        }
+    }
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> AmbientOcclusion {
+        AmbientOcclusion {
+            steps: 4,
+            spacing: fp(1) / fp(2),
+            strength: fp(1) / fp(2),
+        }
+    }
+}
+
+/// How thick fog gets with distance.
+#[derive(Copy, Clone)]
+pub enum FogDensity {
+    /// No fog before `start`, rising evenly to full fog at `end`, or all at once if `end` is not
+    /// past `start`.
+    Linear { start: FP, end: FP },
+    /// Visibility halves every `half_distance`, and is gone right away if that is not positive.
+    Exponential { half_distance: FP },
+}
+
+/// Fog that blends distant surfaces toward a color.
+#[derive(Copy, Clone)]
+pub struct Fog {
+    pub color: Rgb,
+    pub density: FogDensity,
+    /// Fog thins out above this height.
+    pub base: FP,
+    /// Height over `base` at which the fog is half as thick, or zero for no thinning.
+    pub half_height: FP,
+}
+
+impl Fog {
+    /// Amount of fog between 0 and 1 on a ray segment.
+    pub fn amount(&self, ray: &Ray, distance: FP) -> FP {
+        let amount = match self.density {
+            FogDensity::Linear { start, end } if end <= start => {
+                if distance < start { fp(0) } else { fp(1) }
+            }
+            FogDensity::Linear { start, end } => (distance - start) / (end - start),
+            FogDensity::Exponential { half_distance } if half_distance <= fp(0) => fp(1),
+            FogDensity::Exponential { half_distance } => {
+                fp(1) - exp2_neg(distance / half_distance)
+            }
+        };
+        let amount = amount.clamp(fp(0), fp(1));
+        if self.half_height <= fp(0) {
+            return amount;
+        }
+        // Go by the height halfway along the segment.
+        let height = ray.origin.z + ray.dir.z * (distance / fp(2)) - self.base;
+        amount * exp2_neg(max(height, fp(0)) / self.half_height)
+    }
+}
+
+/// 2 to the power of `-x` for non-negative `x`.
+fn exp2_neg(x: FP) -> FP {
+    let whole = x.to_i32();
+    if whole >= 16 {
+        return fp(0);
+    }
+    // Quadratic through the ends and the middle of the fractional power.
+    let f = x - fp(whole);
+    let frac = fp(1) - f * (fp(2) / fp(3) - f / fp(6));
+    FP(frac.0 >> whole)
+}
+
+/// Parameters for `trace`.
+#[derive(Copy, Clone)]
+pub struct TraceSettings<'a> {
+    pub march: MarchSettings,
+    /// How far reflected rays and shadow rays start off the surface, so they don't hit it again.
+    ///
+    /// This needs to be larger than the hit epsilon of the march settings.
+    pub surface_offset: FP,
+    /// Reflections and refractions deeper than this show black.
+    pub max_reflections: usize,
+    /// Color of the rays that escape the scene.
+    pub background: Background<'a>,
+    pub occlusion: AmbientOcclusion,
+    /// Penumbra sharpness, see `penumbra`.
+    pub shadow_hardness: FP,
+    /// Light above which `Material::Surface` turns to its highlight color.
+    pub highlight: FP,
+    /// Light at or below which `Material::Surface` shows its shadow color.
+    pub shadow: FP,
+    /// Fog over the surfaces and the background, or `None` for clear air.
+    pub fog: Option<Fog>,
+}
+
+impl<'a> TraceSettings<'a> {
+    /// Cheap settings for rendering on a microcontroller.
+    pub fn fast() -> TraceSettings<'a> {
+        TraceSettings {
+            march: MarchSettings {
+                max_steps: 96,
+                hit_epsilon: FP(8),
+                ..MarchSettings::default()
+            },
+            surface_offset: FP(16),
+            max_reflections: 2,
+            background: Background::sky(),
+            occlusion: AmbientOcclusion::none(),
+            shadow_hardness: fp(8),
+            highlight: fp(1) / fp(2),
+            shadow: fp(0),
+            fog: None,
+        }
+    }
+
+    /// Settings for rendering on a host where time is not an issue.
+    pub fn quality() -> TraceSettings<'a> {
+        TraceSettings {
+            march: MarchSettings {
+                max_steps: 512,
+                relaxation: fp(6) / fp(5),
+                ..MarchSettings::default()
+            },
+            max_reflections: 8,
+            occlusion: AmbientOcclusion::default(),
+            ..TraceSettings::fast()
+        }
+    }
+}
+
+impl<'a> Default for TraceSettings<'a> {
+    fn default() -> TraceSettings<'a> {
+        TraceSettings::quality()
+    }
+}
+
+/// Disk drawn in the sky toward every directional light.
+#[derive(Copy, Clone)]
+pub struct Sun {
+    pub color: Rgb,
+    /// Angular radius of the disk.
+    pub radius: FP,
+}
+
+/// What rays that escape the scene see.
+#[derive(Copy, Clone)]
+pub enum Background<'a> {
+    Solid(Rgb),
+    /// Gradient from `horizon` to `zenith` straight up, turning to `ground` below the horizon.
+    Sky {
+        zenith: Rgb,
+        horizon: Rgb,
+        ground: Rgb,
+        sun: Option<Sun>,
+    },
+    /// Color from a function of the ray.
+    Custom(&'a dyn Fn(&Ray) -> Rgb),
+}
+
+impl<'a> Background<'a> {
+    /// Blue sky over a dark ground, with a sun.
+    pub fn sky() -> Background<'a> {
+        Background::Sky {
+            zenith: Rgb::from(Color::Blue),
+            horizon: Rgb::from(Color::Cyan),
+            ground: Rgb::gray(fp(1) / fp(4)),
+            sun: Some(Sun {
+                color: Rgb::new(fp(1), fp(1), fp(3) / fp(4)),
+                radius: fp(1) / fp(16),
+            }),
+        }
+    }
+
+    /// Color seen along a ray that escaped a scene with `lights`.
+    pub fn color(&self, ray: &Ray, lights: &[Light]) -> Rgb {
+        match *self {
+            Background::Solid(color) => color,
+            Background::Sky { zenith, horizon, ground, sun } => {
+                if let Some(sun) = sun {
+                    let edge = sun.radius.cos();
+                    for light in lights {
+                        if let Light::Directional { dir, .. } = *light {
+                            if -ray.dir.dot(&dir) > edge {
+                                return sun.color;
+                            }
+                        }
+                    }
+                }
+                let z = ray.dir.z;
+                if z >= fp(0) {
+                    horizon.mix(zenith, z)
+                } else {
+                    horizon.mix(ground, ramp(-z, fp(0)))
                 }
-                Material::Surface(highlight, col, shadow) => {
-                    let light_angle = light_dir.dot(&normal);
-                    return if light_angle < fp(-8) / fp(16) {
-                        highlight
-                    } else if light_angle < fp(0) {
-                        col
+            }
+            Background::Custom(f) => f(ray),
+        }
+    }
+}
+
+/// Counters from tracing a single ray.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub struct TraceStats {
+    /// March steps taken over the ray and all of its reflections.
+    pub steps: usize,
+    /// Deepest reflection or refraction that was followed.
+    pub reflections: usize,
+}
+
+/// Trace a ray and quantize the result to the 3-bit palette.
+pub fn trace<T: Body>(body: &T, ray: Ray, lights: &[Light], settings: &TraceSettings) -> Color {
+    trace_rgb(body, ray, lights, settings).quantize()
+}
+
+pub fn trace_rgb<T: Body>(body: &T, ray: Ray, lights: &[Light], settings: &TraceSettings) -> Rgb {
+    trace_stats(body, ray, lights, settings).0
+}
+
+/// Trace a ray and count the work it took.
+pub fn trace_stats<T: Body>(body: &T,
+                            ray: Ray,
+                            lights: &[Light],
+                            settings: &TraceSettings)
+                            -> (Rgb, TraceStats) {
+    let mut stats = TraceStats::default();
+    let color = trace_ray(body, &ray, lights, settings, 0, false, &mut stats);
+    (color, stats)
+}
+
+/// The inside of a body, for marching rays through glass.
+struct Inside<'a, T: 'a>(&'a T);
+
+impl<'a, T: Body> Body for Inside<'a, T> {
+    fn distance(&self, pos: &V3) -> FP {
+        -self.0.distance(pos)
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.0.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        let (d, id) = self.0.query(pos);
+        (-d, id)
+    }
+}
+
+fn trace_ray<T: Body>(body: &T,
+                      ray: &Ray,
+                      lights: &[Light],
+                      settings: &TraceSettings,
+                      depth: usize,
+                      inside: bool,
+                      stats: &mut TraceStats)
+                      -> Rgb {
+    stats.reflections = max(stats.reflections, depth);
+    let (marched, steps) = if inside {
+        march(&Inside(body), ray, &settings.march)
+    } else {
+        march(body, ray, &settings.march)
+    };
+    stats.steps += steps;
+    let (advance, id) = match marched {
+        Marched::Hit(advance, id) => (advance, id),
+        Marched::Escaped => {
+            let background = settings.background.color(ray, lights);
+            return fog(settings, ray, settings.march.max_distance, background);
+        }
+        Marched::OutOfSteps => return Rgb::gray(fp(0)),
+    };
+    let pos = ray.origin + ray.dir * advance;
+    // Facing the side the ray came from.
+    let normal = if inside {
+        -body.leaf_normal(id, &pos)
+    } else {
+        body.leaf_normal(id, &pos)
+    };
+
+    // Mirrors facing each other go black when they run out of reflections.
+    let reflection = |stats: &mut TraceStats| {
+        if depth >= settings.max_reflections {
+            return Rgb::gray(fp(0));
+        }
+        let reflect = Ray {
+            // Deflect a bit off the surface so we don't get stuck inside it...
+            origin: pos + normal * settings.surface_offset,
+            dir: (ray.dir - normal * (fp(2) * ray.dir.dot(&normal))).normalized(),
+        };
+        trace_ray(body, &reflect, lights, settings, depth + 1, inside, stats)
+    };
+
+    let ctx = SurfaceContext {
+        pos,
+        normal,
+        dir: ray.dir,
+        uv: body.leaf_uv(id, &pos),
+        id,
+    };
+    let color = match body.leaf_material(id, &ctx) {
+        Material::Mirror => reflection(stats),
+        Material::Tinted(tint) => reflection(stats) * Rgb::from(tint),
+        Material::Glass(ior) => {
+            // Keep the index off zero so the ratios below stay finite.
+            let ior = ior.max(FP(1));
+            let (n1, n2) = if inside { (ior, fp(1)) } else { (fp(1), ior) };
+            match refract(&ray.dir, &normal, n1 / n2) {
+                Some((dir, cos)) => {
+                    let refraction = if depth >= settings.max_reflections {
+                        Rgb::gray(fp(0))
                     } else {
-                        shadow
+                        // Hits can be short of the surface by the pixel footprint, make sure to
+                        // cross it.
+                        let gap = body.distance(&pos).abs();
+                        let refract = Ray {
+                            origin: pos - normal * (settings.surface_offset + gap),
+                            dir,
+                        };
+                        trace_ray(body, &refract, lights, settings, depth + 1, !inside, stats)
                     };
+                    let r = fresnel(n1, n2, cos);
+                    // Skip tracing reflections that are too faint to matter.
+                    if r < fp(1) / fp(16) {
+                        refraction
+                    } else {
+                        refraction.mix(reflection(stats), r)
+                    }
                 }
+                // Total internal reflection.
+                None => reflection(stats),
             }
         }
-
-        if advance > ESCAPE_VELOCITY {
-            break;
+        Material::Finish(f) => {
+            let open = ambient_occlusion(body, &pos, &normal, &settings.occlusion);
+            let local = f.shade(illumination(body, &pos, &normal, lights, settings) * open);
+            if f.reflectivity > fp(0) {
+                local.mix(reflection(stats), f.reflectivity)
+            } else {
+                local
+            }
         }
+        Material::Surface(highlight, col, shadow) => {
+            let open = ambient_occlusion(body, &pos, &normal, &settings.occlusion);
+            let light = illumination(body, &pos, &normal, lights, settings) * open;
+            let lit = Rgb::from(shadow)
+                .mix(Rgb::from(col), ramp(light, settings.shadow))
+                .mix(Rgb::from(highlight), ramp(light, settings.highlight));
+            Rgb::from(shadow).mix(lit, ramp(open, fp(1) / fp(2)))
+        }
+    };
+    fog(settings, ray, advance, color)
+}
 
-        advance = advance + d;
+fn fog(settings: &TraceSettings, ray: &Ray, distance: FP, color: Rgb) -> Rgb {
+    match settings.fog {
+        Some(f) => color.mix(f.color, f.amount(ray, distance)),
+        None => color,
+    }
+}
+
+/// Blend factor that rises from 0 at `edge` to 1 a short way above it.
+fn ramp(x: FP, edge: FP) -> FP {
+    ((x - edge) * fp(8)).clamp(fp(0), fp(1))
+}
+
+/// Direction of a ray bent through a surface with the refraction index ratio `eta`.
+///
+/// Return the new direction and the cosine of the angle from the normal on the side with the
+/// lower refraction index, or nothing for total internal reflection.
+fn refract(dir: &V3, normal: &V3, eta: FP) -> Option<(V3, FP)> {
+    let cos_i = -dir.dot(normal);
+    let k = fp(1) - eta * eta * (fp(1) - cos_i * cos_i);
+    if k < fp(0) {
+        return None;
+    }
+    let cos_t = k.sqrt();
+    let dir = (*dir * eta + *normal * (eta * cos_i - cos_t)).normalized();
+    Some((dir, if eta > fp(1) { cos_t } else { cos_i }))
+}
+
+/// Fraction of light reflected off a surface between refraction indices, Schlick's
+/// approximation.
+fn fresnel(n1: FP, n2: FP, cos: FP) -> FP {
+    let r0 = (n1 - n2) / (n1 + n2);
+    let r0 = r0 * r0;
+    let x = fp(1) - cos;
+    r0 + (fp(1) - r0) * x * x * x * x * x
+}
+
+/// Light falling on a surface point from all the lights, with shadows.
+fn illumination<T: Body>(body: &T,
+                         pos: &V3,
+                         normal: &V3,
+                         lights: &[Light],
+                         settings: &TraceSettings)
+                         -> FP {
+    let start = *pos + *normal * settings.surface_offset;
+    lights.iter()
+        .map(|light| {
+            let (to_light, dist, intensity) = light.illuminate(pos);
+            let angle = normal.dot(&to_light);
+            if angle <= fp(0) || intensity <= fp(0) {
+                return fp(0);
+            }
+            intensity * angle * penumbra(body, &start, &to_light, dist, settings)
+        })
+        .fold(fp(0), |a, b| a + b)
+}
+
+/// How open the surface at `pos` is to ambient light, from 0 in a closed crease to 1 in the open.
+pub fn ambient_occlusion<T: Body>(body: &T,
+                                  pos: &V3,
+                                  normal: &V3,
+                                  settings: &AmbientOcclusion)
+                                  -> FP {
+    let mut occlusion = fp(0);
+    // Samples further away from the surface count less.
+    let mut weight = fp(1);
+    for i in 1..settings.steps + 1 {
+        let h = settings.spacing * fp(i as i32);
+        let d = body.distance(&(*pos + *normal * h));
+        occlusion = occlusion + (h - d) * weight;
+        weight = weight / fp(2);
+    }
+    (fp(1) - occlusion * settings.strength).clamp(fp(0), fp(1))
+}
 
-        n_steps += 1;
-        if n_steps > MAX_STEPS {
-            return Color::Black;
+/// Whether anything blocks the path from `pos` toward a light `max_dist` away.
+///
+/// `to_light` is the unit direction pointing at the light, and `pos` should be just off the
+/// surface the path starts from.
+pub fn is_occluded<T: Body>(body: &T,
+                            pos: &V3,
+                            to_light: &V3,
+                            max_dist: FP,
+                            settings: &TraceSettings)
+                            -> bool {
+    let max_dist = min(max_dist, settings.march.max_distance);
+    let mut advance = settings.surface_offset;
+    for _ in 0..settings.march.max_steps {
+        let d = body.distance(&(*pos + *to_light * advance));
+        if d <= FP(0) {
+            return true;
+        }
+        advance = advance + d;
+        if advance > max_dist {
+            return false;
         }
     }
+    false
+}
 
-    // Hack things a bit to show a blue sky in mirrors so they aren't quite that invisible.
-    if is_mirror {
-        Color::Blue
-    } else {
-        Color::Cyan
+/// Fraction of the light `max_dist` away that reaches `pos` from the direction `to_light`, from
+/// 0 to 1.
+///
+/// A blocked path gives 0. Paths that pass close to a surface give a soft penumbra, which gets
+/// narrower as `shadow_hardness` grows.
+pub fn penumbra<T: Body>(body: &T,
+                         pos: &V3,
+                         to_light: &V3,
+                         max_dist: FP,
+                         settings: &TraceSettings)
+                         -> FP {
+    let max_dist = min(max_dist, settings.march.max_distance);
+    let mut light = fp(1);
+    let mut advance = settings.surface_offset;
+    for _ in 0..settings.march.max_steps {
+        let d = body.distance(&(*pos + *to_light * advance));
+        if d <= FP(0) {
+            return fp(0);
+        }
+        light = min(light, settings.shadow_hardness * d / advance);
+        advance = advance + d;
+        if advance > max_dist {
+            break;
+        }
     }
+    light
 }
 
 /// Wrapper that turns a material into a constant function.
-pub fn m(m: Material) -> impl Fn(&V3) -> Material {
+pub fn m(m: Material) -> impl Fn(&SurfaceContext) -> Material {
     move |_| m
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{Filter, ImageTexture};
+    use super::*;
+
+    #[test]
+    fn test_color() {
+        assert!(Color::Red.mix(Color::Blue, fp(0)) == Color::Red);
+        assert!(Color::Red.mix(Color::Blue, fp(1) / fp(4)) == Color::Red);
+        assert!(Color::Red.mix(Color::Blue, fp(1) / fp(2)) == Color::Magenta);
+        assert!(Color::Yellow.mix(Color::Cyan, fp(3) / fp(4)) == Color::Cyan);
+        assert!(Color::White.tint(Color::Yellow) == Color::Yellow);
+        assert!(Color::Cyan.tint(Color::Yellow) == Color::Green);
+        assert!(Color::from_bits(Color::Magenta as u8) == Color::Magenta);
+    }
+
+    #[test]
+    fn test_shadow() {
+        let scene = Scene + Object::new(sphere_fn(v3(0, 0, 10), fp(2)), m(Material::Mirror));
+        let up = v3(0, 0, 1);
+
+        let far = fp(1000);
+        let settings = TraceSettings::default();
+
+        assert!(is_occluded(&scene, &v3(0, 0, 0), &up, far, &settings));
+        assert!(!is_occluded(&scene, &v3(5, 0, 0), &up, far, &settings));
+        assert!(!is_occluded(&scene, &v3(0, 0, 0), &-up, far, &settings));
+        // Light between the point and the sphere.
+        assert!(!is_occluded(&scene, &v3(0, 0, 0), &up, fp(5), &settings));
+
+        assert!(penumbra(&scene, &v3(0, 0, 0), &up, far, &settings) == fp(0));
+        assert!(penumbra(&scene, &v3(20, 0, 0), &up, far, &settings) == fp(1));
+        // Passing close by the sphere is partly lit.
+        let edge = penumbra(&scene, &v3(3, 0, 0), &up, far, &settings);
+        assert!(edge > fp(0) && edge < fp(1));
+        let soft = TraceSettings { shadow_hardness: fp(2), ..settings };
+        assert!(penumbra(&scene, &v3(3, 0, 0), &up, far, &soft) < edge);
+    }
+
+    #[test]
+    fn test_lights() {
+        let ball = Object::new(sphere_fn(v3(10, 0, 0), fp(2)),
+                               m(Material::Surface(Color::White, Color::Red, Color::Black)));
+        let scene = Scene + ball;
+        let ray = Ray {
+            origin: v3(0, 0, 0),
+            dir: v3(1, 0, 0),
+        };
+        let back = Light::Directional {
+            dir: v3(-1, 0, 0),
+            intensity: fp(1),
+        };
+        let fill = Light::Point {
+            pos: v3(0, 0, 0),
+            intensity: fp(1) / fp(4),
+            range: fp(0),
+        };
+        let front = Light::Directional {
+            dir: v3(1, 0, 0),
+            intensity: fp(1),
+        };
+        let settings = TraceSettings::fast();
+        // Key light from behind the ball, the fill light shows the side facing the camera.
+        assert!(trace(&scene, ray, &[back], &settings) == Color::Black);
+        assert!(trace(&scene, ray, &[back, fill], &settings) == Color::Red);
+        assert!(trace(&scene, ray, &[front, fill], &settings) == Color::White);
+    }
+
+    #[test]
+    fn test_surface_context() {
+        let flat = |c| Material::Surface(c, c, c);
+        // Top half red by the normal, the rest by the incoming ray.
+        let ball = Object::new(sphere_fn(v3(10, 0, 0), fp(4)), move |ctx: &SurfaceContext| {
+            if ctx.normal.z > fp(1) / fp(2) {
+                flat(Color::Red)
+            } else if ctx.dir.y > fp(0) {
+                flat(Color::Green)
+            } else {
+                flat(Color::Blue)
+            }
+        });
+        let scene = Scene + ball;
+        let ray = |x, y, z| {
+            Ray {
+                origin: v3(0, 0, 0),
+                dir: v3(x, y, z).normalized(),
+            }
+        };
+        let settings = TraceSettings::fast();
+        assert!(trace(&scene, ray(10, 0, 4), &[], &settings) == Color::Red);
+        assert!(trace(&scene, ray(10, 1, 0), &[], &settings) == Color::Green);
+        assert!(trace(&scene, ray(10, -1, 0), &[], &settings) == Color::Blue);
+
+        let striped = at_pos(texture::select(texture::stripes(texture::Mapping::default()),
+                                             flat(Color::Red),
+                                             flat(Color::Cyan)));
+        match striped(&SurfaceContext::at(v3(1, 0, 0))) {
+            Material::Surface(Color::Cyan, _, _) => {}
+            _ => panic!("Wrong material"),
+        }
+    }
+
+    #[test]
+    fn test_bump() {
+        let floor = || {
+            Object::new(plane_fn(v3(0, 0, 1), fp(0)),
+                        m(Material::Finish(Finish::matte(Color::White))))
+        };
+        let plain = Scene + floor();
+        let bumpy = Scene + Bump::new(floor(), |p: &V3| (p.x * fp(2)).sin() / fp(2), fp(1));
+        let ray = Ray {
+            origin: v3(0, 0, 8),
+            dir: v3(1, 0, -1).normalized(),
+        };
+        let sun = Light::Directional {
+            dir: v3(0, 0, -1),
+            intensity: fp(1),
+        };
+        let settings = TraceSettings::fast();
+        let (smooth, smooth_stats) = trace_stats(&plain, ray, &[sun], &settings);
+        let (rough, rough_stats) = trace_stats(&bumpy, ray, &[sun], &settings);
+        // Tilted away from the light by the bumps, for the same marching.
+        assert!(smooth == Rgb::from(Color::White));
+        assert!(rough.r < smooth.r);
+        assert!(rough_stats.steps == smooth_stats.steps);
+
+        // Height from an image, a black and a white pixel side by side across each unit of x.
+        let pixels = [0, 0, 0, 255, 255, 255];
+        let image = ImageTexture::new(2, 1, &pixels, Filter::Bilinear).unwrap();
+        let embossed = Bump::new(floor(), image.height_map(uv::planar), fp(1) / fp(8));
+        // Brightening toward x in the middle of the unit, so the normal leans back.
+        let middle = V3::new(fp(1) / fp(2), fp(0), fp(0));
+        let normal = embossed.normal(&middle);
+        assert!(normal.x < fp(0) && normal.z > fp(0));
+        // Darkening toward x at the start of the next unit.
+        assert!(embossed.normal(&v3(1, 0, 0)).x > fp(0));
+
+        // Flat fields have no normal to tilt.
+        let fog = Object::new(|_: &V3| fp(1), m(Material::Mirror));
+        assert!(Bump::new(fog, |_: &V3| fp(0), fp(1)).normal(&middle) == v3(0, 0, 0));
+    }
+
+    #[test]
+    fn test_settings() {
+        let (fast, quality) = (TraceSettings::fast(), TraceSettings::quality());
+        assert!(fast.march.max_steps < quality.march.max_steps);
+        assert!(fast.march.hit_epsilon > quality.march.hit_epsilon);
+        assert!(fast.surface_offset > fast.march.hit_epsilon);
+        assert!(fast.max_reflections < quality.max_reflections);
+        assert!(TraceSettings::default().max_reflections == quality.max_reflections);
+
+        // Rays that miss everything show the background.
+        let ray = Ray {
+            origin: v3(0, 0, 0),
+            dir: v3(1, 0, 0),
+        };
+        let settings = TraceSettings {
+            background: Background::Solid(Rgb::from(Color::Magenta)),
+            ..fast
+        };
+        assert!(trace(&Scene, ray, &[], &settings) == Color::Magenta);
+    }
+
+    #[test]
+    fn test_reflections() {
+        let front = Object::new(plane_fn(v3(-1, 0, 0), fp(-10)), m(Material::Mirror));
+        let back = Object::new(plane_fn(v3(1, 0, 0), fp(-10)), m(Material::Mirror));
+        let ray = Ray {
+            origin: v3(0, 0, 0),
+            dir: v3(4, 0, 1).normalized(),
+        };
+        let settings = TraceSettings {
+            background: Background::Solid(Rgb::from(Color::Blue)),
+            ..TraceSettings::default()
+        };
+
+        assert!(trace(&(Scene + &front), ray, &[], &settings) == Color::Blue);
+        // The ray bounces between the mirrors until it runs out of reflections.
+        let corridor = Scene + &front + &back;
+        let (color, stats) = trace_stats(&corridor, ray, &[], &settings);
+        assert!(color.quantize() == Color::Black);
+        assert!(stats.reflections == settings.max_reflections);
+        assert!(stats.steps > stats.reflections);
+        let shallow = TraceSettings { max_reflections: 1, ..settings };
+        assert!(trace_stats(&corridor, ray, &[], &shallow).1.reflections == 1);
+
+        let glossy = |reflectivity| {
+            let finish = Finish {
+                emission: fp(1),
+                reflectivity,
+                ..Finish::matte(Color::Red)
+            };
+            Scene + Object::new(plane_fn(v3(-1, 0, 0), fp(-10)), m(Material::Finish(finish)))
+        };
+        assert!(trace(&glossy(fp(0)), ray, &[], &settings) == Color::Red);
+        assert!(trace(&glossy(fp(1) / fp(2)), ray, &[], &settings) == Color::Magenta);
+        assert!(trace(&glossy(fp(1)), ray, &[], &settings) == Color::Blue);
+
+        fn ground(ray: &Ray) -> Rgb {
+            if ray.dir.z < fp(0) {
+                Rgb::from(Color::Green)
+            } else {
+                Rgb::from(Color::White)
+            }
+        }
+        let settings = TraceSettings { background: Background::Custom(&ground), ..settings };
+        let down = Ray { dir: v3(0, 0, -1), ..ray };
+        assert!(trace(&Scene, down, &[], &settings) == Color::Green);
+        assert!(trace(&Scene, ray, &[], &settings) == Color::White);
+        // Mirrors show the environment.
+        let tilted = Object::new(plane_fn(v3(-1, 0, -1).normalized(), fp(-10)),
+                                 m(Material::Mirror));
+        assert!(trace(&(Scene + tilted), ray, &[], &settings) == Color::Green);
+
+        let gold = Object::new(plane_fn(v3(-1, 0, 0), fp(-10)), m(Material::Tinted(Color::Yellow)));
+        assert!(trace(&(Scene + gold), ray, &[], &settings) == Color::Yellow);
+    }
+
+    #[test]
+    fn test_background() {
+        let sky = Background::Sky {
+            zenith: Rgb::from(Color::Blue),
+            horizon: Rgb::from(Color::White),
+            ground: Rgb::from(Color::Green),
+            sun: Some(Sun {
+                color: Rgb::from(Color::Yellow),
+                radius: fp(1) / fp(8),
+            }),
+        };
+        let ray = |x, y, z| {
+            Ray {
+                origin: v3(0, 0, 0),
+                dir: v3(x, y, z).normalized(),
+            }
+        };
+        assert!(sky.color(&ray(0, 0, 1), &[]) == Rgb::from(Color::Blue));
+        assert!(sky.color(&ray(1, 0, 0), &[]) == Rgb::from(Color::White));
+        let between = sky.color(&ray(1, 0, 1), &[]);
+        assert!(between.r > fp(0) && between.r < fp(1) && between.b == fp(1));
+        assert!(sky.color(&ray(1, 1, -1), &[]) == Rgb::from(Color::Green));
+
+        let sun = Light::Directional {
+            dir: v3(0, -1, -1).normalized(),
+            intensity: fp(1),
+        };
+        assert!(sky.color(&ray(0, 1, 1), &[sun]) == Rgb::from(Color::Yellow));
+        assert!(sky.color(&ray(0, 0, 1), &[sun]) == Rgb::from(Color::Blue));
+        // Only directional lights make suns.
+        let bulb = Light::Point {
+            pos: v3(0, 0, 10),
+            intensity: fp(1),
+            range: fp(0),
+        };
+        assert!(sky.color(&ray(0, 0, 1), &[bulb]) == Rgb::from(Color::Blue));
+
+        // Custom backgrounds can capture.
+        let stripes = [Rgb::from(Color::Red), Rgb::from(Color::Green)];
+        let striped = |ray: &Ray| stripes[(ray.dir.z > fp(0)) as usize];
+        let custom = Background::Custom(&striped);
+        assert!(custom.color(&ray(1, 0, 1), &[]) == Rgb::from(Color::Green));
+        assert!(custom.color(&ray(1, 0, -1), &[]) == Rgb::from(Color::Red));
+    }
+
+    #[test]
+    fn test_fog() {
+        let exp = Fog {
+            color: Rgb::from(Color::White),
+            density: FogDensity::Exponential { half_distance: fp(10) },
+            base: fp(0),
+            half_height: fp(0),
+        };
+        let ray = Ray {
+            origin: v3(0, 0, 0),
+            dir: v3(1, 0, 0),
+        };
+        assert!(exp.amount(&ray, fp(0)) == fp(0));
+        assert!(exp.amount(&ray, fp(10)) == fp(1) / fp(2));
+        assert!(exp.amount(&ray, fp(20)) == fp(3) / fp(4));
+        let near = exp.amount(&ray, fp(5));
+        assert!(near > fp(1) / fp(4) && near < fp(1) / fp(2));
+        assert!(exp.amount(&ray, fp(1000)) == fp(1));
+
+        let linear = Fog {
+            density: FogDensity::Linear { start: fp(10), end: fp(30) },
+            ..exp
+        };
+        assert!(linear.amount(&ray, fp(5)) == fp(0));
+        assert!(linear.amount(&ray, fp(20)) == fp(1) / fp(2));
+        assert!(linear.amount(&ray, fp(40)) == fp(1));
+        // Degenerate densities don't divide by zero.
+        let wall_of_fog = Fog { density: FogDensity::Linear { start: fp(10), end: fp(10) }, ..exp };
+        assert!(wall_of_fog.amount(&ray, fp(9)) == fp(0));
+        assert!(wall_of_fog.amount(&ray, fp(10)) == fp(1));
+        let thick = Fog { density: FogDensity::Exponential { half_distance: fp(0) }, ..exp };
+        assert!(thick.amount(&ray, fp(1)) == fp(1));
+
+        // Thinner up high.
+        let ground = Fog { half_height: fp(5), ..linear };
+        assert!(ground.amount(&ray, fp(40)) == fp(1));
+        let high = Ray { origin: v3(0, 0, 5), ..ray };
+        assert!(ground.amount(&high, fp(40)) == fp(1) / fp(2));
+
+        let wall = Object::new(plane_fn(v3(-1, 0, 0), fp(-20)),
+                               m(Material::Finish(Finish {
+                                   emission: fp(1),
+                                   ..Finish::matte(Color::Blue)
+                               })));
+        let scene = Scene + wall;
+        let settings = TraceSettings { fog: Some(linear), ..TraceSettings::fast() };
+        assert!(trace_rgb(&scene, ray, &[], &settings) ==
+                Rgb::from(Color::Blue).mix(Rgb::from(Color::White), fp(1) / fp(2)));
+        let up = Ray { dir: v3(0, 0, 1), ..ray };
+        assert!(trace(&scene, up, &[], &settings) == Color::White);
+    }
+
+    #[test]
+    fn test_glass() {
+        fn side(ray: &Ray) -> Rgb {
+            Rgb::from(if ray.dir.y < FP(-8) {
+                Color::Green
+            } else if ray.dir.y > FP(8) {
+                Color::Red
+            } else {
+                Color::White
+            })
+        }
+        let settings = TraceSettings {
+            background: Background::Custom(&side),
+            ..TraceSettings::quality()
+        };
+        let ball = Object::new(sphere_fn(v3(10, 0, 0), fp(3)), m(Material::Glass(fp(3) / fp(2))));
+        let scene = Scene + ball;
+
+        // Straight through the middle.
+        let ray = Ray {
+            origin: v3(0, 0, 0),
+            dir: v3(1, 0, 0),
+        };
+        let (color, stats) = trace_stats(&scene, ray, &[], &settings);
+        assert!(color.quantize() == Color::White);
+        assert!(stats.reflections == 2);
+
+        // The ball bends rays above the middle down like a lens.
+        let above = Ray { origin: v3(0, 2, 0), ..ray };
+        assert!(trace(&Scene, above, &[], &settings) == Color::White);
+        assert!(trace(&scene, above, &[], &settings) == Color::Green);
+        let below = Ray { origin: v3(0, -2, 0), ..ray };
+        assert!(trace(&scene, below, &[], &settings) == Color::Red);
+
+        let flat = TraceSettings { max_reflections: 1, ..settings };
+        assert!(trace(&scene, ray, &[], &flat) == Color::Black);
+
+        // Indices that aren't positive don't break the tracer.
+        for &ior in &[fp(0), fp(-1)] {
+            let ball = Object::new(sphere_fn(v3(10, 0, 0), fp(3)), m(Material::Glass(ior)));
+            trace(&(Scene + ball), above, &[], &settings);
+        }
+    }
+
+    #[test]
+    fn test_refract() {
+        let normal = v3(0, 0, 1);
+        let (dir, cos) = refract(&v3(0, 0, -1), &normal, fp(2) / fp(3)).unwrap();
+        assert!(dir == v3(0, 0, -1) && cos == fp(1));
+        // Leaving glass at a shallow angle reflects everything.
+        let shallow = v3(4, 0, -1).normalized();
+        assert!(refract(&shallow, &normal, fp(3) / fp(2)).is_none());
+        assert!(refract(&shallow, &normal, fp(2) / fp(3)).is_some());
+
+        let glass = fp(3) / fp(2);
+        assert!(fresnel(fp(1), glass, fp(1)) < fp(1) / fp(8));
+        assert!(fresnel(fp(1), glass, fp(0)) == fp(1));
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let floor = Object::new(plane_fn(v3(0, 0, 1), fp(0)), m(Material::Mirror));
+        let ball = Object::new(sphere_fn(v3(0, 0, 2), fp(2)), m(Material::Mirror));
+        let scene = Scene + floor + ball;
+        let up = v3(0, 0, 1);
+        let ao = AmbientOcclusion::default();
+
+        assert!(ambient_occlusion(&scene, &v3(20, 0, 0), &up, &ao) == fp(1));
+        // Next to the contact point of the ball.
+        let contact = ambient_occlusion(&scene, &v3(1, 0, 0), &up, &ao);
+        assert!(contact < fp(1) / fp(2));
+        assert!(ambient_occlusion(&scene, &v3(3, 0, 0), &up, &ao) > contact);
+        assert!(ambient_occlusion(&scene, &v3(1, 0, 0), &up, &AmbientOcclusion::none()) == fp(1));
+    }
+
+    #[test]
+    fn test_grad() {
+        let n = v3(3, 0, 0).grad(|p| sphere_fn(v3(0, 0, 0), fp(3))(&p));
+        assert!(n.x > fp(1) - FP(4) && n.x <= fp(1));
+        assert!(n.y == fp(0) && n.z == fp(0));
+        assert!(v3(1, 2, 3).grad(|_| fp(1)) == v3(0, 0, 0));
+    }
+}
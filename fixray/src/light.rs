@@ -0,0 +1,198 @@
+//! Light sources.
+
+use core::ops::Deref;
+
+use fp::{fp, FP};
+use v::{v3, V3};
+
+/// Default number of lights in a scene.
+pub const MAX_LIGHTS: usize = 4;
+
+#[derive(Copy, Clone)]
+pub enum Light {
+    /// Parallel light from far away, traveling along `dir`.
+    Directional { dir: V3, intensity: FP },
+    /// Light shining in every direction, fading to half its intensity at `range`.
+    Point { pos: V3, intensity: FP, range: FP },
+    /// Point light limited to a cone of half-angle `angle` around `dir`.
+    Spot {
+        pos: V3,
+        dir: V3,
+        angle: FP,
+        intensity: FP,
+        range: FP,
+    },
+}
+
+impl Light {
+    /// Light reaching `pos`.
+    ///
+    /// Return the unit direction toward the light, the distance to it and the intensity after
+    /// falloff.
+    pub fn illuminate(&self, pos: &V3) -> (V3, FP, FP) {
+        match *self {
+            Light::Directional { dir, intensity } => (-dir, FP(i32::MAX), intensity),
+            Light::Point { pos: p, intensity, range } => {
+                let (to_light, dist) = toward(pos, &p);
+                (to_light, dist, attenuate(intensity, dist, range))
+            }
+            Light::Spot { pos: p, dir, angle, intensity, range } => {
+                let (to_light, dist) = toward(pos, &p);
+                // Fade from full at the axis to nothing at the edge of the cone. A cone too narrow
+                // for the fixed-point cosine gives no light.
+                let edge = angle.cos();
+                let cos = -to_light.dot(&dir);
+                let cone = if cos <= edge || edge >= fp(1) {
+                    fp(0)
+                } else {
+                    (cos - edge) / (fp(1) - edge)
+                };
+                (to_light, dist, attenuate(intensity, dist, range) * cone)
+            }
+        }
+    }
+}
+
+fn toward(from: &V3, to: &V3) -> (V3, FP) {
+    let d = *to - *from;
+    let dist = d.abs();
+    if dist == fp(0) {
+        (v3(0, 0, 1), dist)
+    } else {
+        (d.map(|c| c / dist), dist)
+    }
+}
+
+fn attenuate(intensity: FP, dist: FP, range: FP) -> FP {
+    if range <= fp(0) {
+        return intensity;
+    }
+    let r = dist / range;
+    intensity / (fp(1) + r * r)
+}
+
+/// Fixed-capacity list of the lights in a scene.
+///
+/// Dereferences to a slice of the lights that have been added.
+#[derive(Copy, Clone)]
+pub struct Lights<const N: usize> {
+    lights: [Light; N],
+    len: usize,
+}
+
+impl<const N: usize> Lights<N> {
+    pub fn new() -> Lights<N> {
+        Lights {
+            lights: [Light::Directional {
+                dir: v3(0, 0, -1),
+                intensity: fp(0),
+            }; N],
+            len: 0,
+        }
+    }
+
+    /// Add a light, or give it back if the list is full.
+    pub fn push(&mut self, light: Light) -> Result<(), Light> {
+        if self.len == N {
+            return Err(light);
+        }
+        self.lights[self.len] = light;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut Light> {
+        self.lights[..self.len].get_mut(idx)
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> Default for Lights<N> {
+    fn default() -> Lights<N> {
+        Lights::new()
+    }
+}
+
+impl<const N: usize> Deref for Lights<N> {
+    type Target = [Light];
+
+    fn deref(&self) -> &[Light] {
+        &self.lights[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::HALF_PI;
+    use super::*;
+
+    #[test]
+    fn test_illuminate() {
+        let sun = Light::Directional {
+            dir: v3(0, 0, -1),
+            intensity: fp(1),
+        };
+        let (to_light, _, intensity) = sun.illuminate(&v3(5, 5, 0));
+        assert!(to_light == v3(0, 0, 1));
+        assert!(intensity == fp(1));
+
+        let bulb = Light::Point {
+            pos: v3(0, 0, 10),
+            intensity: fp(1),
+            range: fp(10),
+        };
+        let (to_light, dist, intensity) = bulb.illuminate(&v3(0, 0, 0));
+        assert!(to_light == v3(0, 0, 1));
+        assert!(dist == fp(10));
+        assert!(intensity == fp(1) / fp(2));
+        assert!(bulb.illuminate(&v3(0, 0, 30)).2 < intensity);
+
+        let spot = Light::Spot {
+            pos: v3(0, 0, 10),
+            dir: v3(0, 0, -1),
+            angle: HALF_PI / fp(2),
+            intensity: fp(1),
+            range: fp(0),
+        };
+        assert!(spot.illuminate(&v3(0, 0, 0)).2 == fp(1));
+        let edge = spot.illuminate(&v3(5, 0, 0)).2;
+        assert!(edge > fp(0) && edge < fp(1));
+        assert!(spot.illuminate(&v3(20, 0, 0)).2 == fp(0));
+
+        // Cones narrower than the cosine can resolve.
+        for a in -16..16 {
+            let beam = Light::Spot {
+                pos: v3(0, 0, 10),
+                dir: v3(0, 0, -1),
+                angle: FP(a),
+                intensity: fp(1),
+                range: fp(0),
+            };
+            assert!(beam.illuminate(&v3(0, 0, 0)).2 <= fp(1));
+            assert!(beam.illuminate(&v3(1, 0, 0)).2 == fp(0));
+        }
+    }
+
+    #[test]
+    fn test_lights() {
+        let mut lights: Lights<2> = Lights::new();
+        assert!(lights.is_empty());
+        let sun = Light::Directional {
+            dir: v3(0, 0, -1),
+            intensity: fp(1),
+        };
+        assert!(lights.push(sun).is_ok());
+        assert!(lights.push(sun).is_ok());
+        assert!(lights.push(sun).is_err());
+        assert!(lights.len() == 2);
+        lights.clear();
+        assert!(lights.iter().count() == 0);
+    }
+}
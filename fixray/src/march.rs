@@ -0,0 +1,170 @@
+//! Sphere tracing of rays against a distance field.
+
+use core::cmp::{max, min};
+
+use fp::{fp, FP};
+use geom::Ray;
+use scene::{Body, ObjectId};
+
+/// Settings for marching a ray.
+#[derive(Copy, Clone)]
+pub struct MarchSettings {
+    pub max_steps: usize,
+    /// Rays closer than this to a surface hit it.
+    pub hit_epsilon: FP,
+    /// Rays that get this far without hitting anything escape.
+    pub max_distance: FP,
+    /// Factor for the step lengths, 1 is plain sphere tracing.
+    ///
+    /// Longer steps reach far surfaces in fewer steps. A step that jumps past a surface is
+    /// retaken without the factor.
+    pub relaxation: FP,
+    /// Width of a pixel at distance 1.
+    ///
+    /// A ray hits a surface when it's closer than half a pixel, so distant surfaces are found
+    /// with fewer steps without changing how they look.
+    pub pixel_size: FP,
+}
+
+impl MarchSettings {
+    /// Plain sphere tracing that only stops at the surface.
+    pub fn exact() -> MarchSettings {
+        MarchSettings {
+            max_steps: 256,
+            hit_epsilon: fp(0),
+            max_distance: fp(1000),
+            relaxation: fp(1),
+            pixel_size: fp(0),
+        }
+    }
+}
+
+impl Default for MarchSettings {
+    /// Settings for the pixel rows of `Frustum::ray`.
+    fn default() -> MarchSettings {
+        MarchSettings {
+            max_steps: 256,
+            hit_epsilon: fp(0),
+            max_distance: fp(1000),
+            relaxation: fp(3) / fp(2),
+            pixel_size: fp(1) / fp(32),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Marched {
+    /// The ray hit a leaf at this distance along the ray.
+    Hit(FP, ObjectId),
+    Escaped,
+    OutOfSteps,
+}
+
+/// March a ray until it hits the body, return the result and the number of steps taken.
+pub fn march<T: Body>(body: &T, ray: &Ray, settings: &MarchSettings) -> (Marched, usize) {
+    let mut advance = fp(0);
+    let mut relaxation = settings.relaxation;
+    let mut step = fp(0);
+    let mut prev_d = fp(0);
+
+    for n in 0..settings.max_steps {
+        let (d, id) = body.query(&(ray.origin + ray.dir * advance));
+        // Keep empty space from overflowing the step arithmetic.
+        let d = min(d, settings.max_distance);
+
+        // The free spheres around consecutive points must overlap, otherwise the relaxed step
+        // may have jumped over a surface.
+        if relaxation > fp(1) && d + prev_d < step {
+            advance = advance - step + prev_d;
+            step = prev_d;
+            relaxation = fp(1);
+            continue;
+        }
+
+        if d <= max(settings.hit_epsilon, advance * settings.pixel_size / fp(2)) {
+            return (Marched::Hit(advance, id), n + 1);
+        }
+        if advance > settings.max_distance {
+            return (Marched::Escaped, n + 1);
+        }
+
+        prev_d = d;
+        step = d * relaxation;
+        advance = advance + step;
+    }
+    (Marched::OutOfSteps, settings.max_steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use v::{v3, V3};
+    use scene::{Object, Scene};
+    use shape::{box_fn, plane_fn, sphere_fn};
+    use {m, Material};
+    use super::*;
+
+    #[test]
+    fn test_march() {
+        let floor = Object::new(plane_fn(v3(0, 0, 1), fp(0)), m(Material::Mirror));
+        let ball = Object::new(sphere_fn(v3(100, 0, 10), fp(2)), m(Material::Mirror));
+        let scene = Scene + floor + ball;
+        let exact = MarchSettings::exact();
+        let fast = MarchSettings::default();
+
+        // Straight at the ball.
+        let ray = Ray {
+            origin: v3(0, 0, 10),
+            dir: v3(1, 0, 0),
+        };
+        let (hit, exact_steps) = march(&scene, &ray, &exact);
+        assert!(hit == Marched::Hit(fp(98), ObjectId(2)));
+        let (hit, fast_steps) = march(&scene, &ray, &fast);
+        match hit {
+            Marched::Hit(t, ObjectId(2)) => assert!(t > fp(95) && t <= fp(98)),
+            _ => panic!("Missed"),
+        }
+        assert!(fast_steps <= exact_steps);
+
+        // Grazing the floor takes many small steps.
+        let ray = Ray {
+            origin: v3(0, 0, 1),
+            dir: v3(64, 0, -1).normalized(),
+        };
+        let (hit, exact_steps) = march(&scene, &ray, &exact);
+        assert!(hit == Marched::OutOfSteps || exact_steps > 20);
+        let (hit, fast_steps) = march(&scene, &ray, &fast);
+        match hit {
+            // Within half a pixel of the floor from halfway on.
+            Marched::Hit(t, ObjectId(1)) => assert!(t >= fp(31) && t <= fp(64)),
+            _ => panic!("Missed"),
+        }
+        assert!(fast_steps < exact_steps);
+
+        let up = Ray {
+            origin: v3(0, 0, 1),
+            dir: v3(0, 0, 1),
+        };
+        assert!(march(&scene, &up, &fast).0 == Marched::Escaped);
+    }
+
+    #[test]
+    fn test_overshoot() {
+        // A thin wall right behind a wide open space, a relaxed step would jump over it.
+        let wall = Object::new(box_fn(V3::new(fp(10) + FP(32), fp(0), fp(0)),
+                                      V3::new(FP(32), fp(50), fp(50))),
+                               m(Material::Mirror));
+        let scene = Scene + wall;
+        let ray = Ray {
+            origin: v3(0, 0, 0),
+            dir: v3(1, 0, 0),
+        };
+        let settings = MarchSettings {
+            relaxation: fp(19) / fp(10),
+            ..MarchSettings::exact()
+        };
+        match march(&scene, &ray, &settings).0 {
+            Marched::Hit(t, _) => assert!(t <= fp(10)),
+            _ => panic!("Missed"),
+        }
+    }
+}
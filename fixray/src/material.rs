@@ -0,0 +1,200 @@
+//! Named materials that objects refer to by index.
+
+use fp::{fp, FP};
+use rgb::Rgb;
+use scene::SurfaceContext;
+use {Color, Material, Paint};
+
+/// Default number of entries in a scene's material table.
+pub const MAX_MATERIALS: usize = 16;
+
+/// Longest material name a table can store.
+pub const MAX_NAME: usize = 15;
+
+/// Index of an entry in a `MaterialTable`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MaterialId(pub usize);
+
+/// Surface described by its physical properties.
+///
+/// The fractions are between 0 and 1. The color of the surface is blended with its reflection by
+/// the reflectivity.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Finish {
+    pub albedo: Rgb,
+    pub reflectivity: FP,
+    /// Size of the white highlight facing the light.
+    pub specular: FP,
+    /// Light given off by the surface itself.
+    pub emission: FP,
+}
+
+impl Finish {
+    /// Matte surface of a color.
+    pub fn matte(albedo: Color) -> Finish {
+        Finish {
+            albedo: Rgb::from(albedo),
+            reflectivity: fp(0),
+            specular: fp(0),
+            emission: fp(0),
+        }
+    }
+
+    /// Color when `light` is the amount of light falling on the surface.
+    pub fn shade(&self, light: FP) -> Rgb {
+        let lit = (self.emission + light).clamp(fp(0), fp(1));
+        let diffuse = self.albedo * lit;
+        if self.specular > fp(0) {
+            // Fade to white over the top `specular` of the light.
+            let amount = (lit - (fp(1) - self.specular)) / self.specular;
+            diffuse.mix(Rgb::gray(fp(1)), amount.clamp(fp(0), fp(1)))
+        } else {
+            diffuse
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    name: [u8; MAX_NAME],
+    name_len: usize,
+    paint: Paint,
+}
+
+/// Fixed-size registry of named materials.
+///
+/// Entries are numbered in the order they are added and keep their ids when redefined.
+pub struct MaterialTable<const N: usize> {
+    entries: [Option<Entry>; N],
+}
+
+impl<const N: usize> MaterialTable<N> {
+    pub fn new() -> MaterialTable<N> {
+        MaterialTable { entries: [None; N] }
+    }
+
+    /// Add a material or redefine the one with the same name.
+    ///
+    /// Return `None` if the table is full or the name is longer than `MAX_NAME`.
+    pub fn insert(&mut self, name: &[u8], paint: Paint) -> Option<MaterialId> {
+        if name.len() > MAX_NAME {
+            return None;
+        }
+        if let Some(id) = self.find(name) {
+            self.entries[id.0].as_mut().unwrap().paint = paint;
+            return Some(id);
+        }
+        let idx = self.len();
+        if idx == N {
+            return None;
+        }
+        let mut entry = Entry {
+            name: [0; MAX_NAME],
+            name_len: name.len(),
+            paint,
+        };
+        entry.name[..name.len()].copy_from_slice(name);
+        self.entries[idx] = Some(entry);
+        Some(MaterialId(idx))
+    }
+
+    pub fn find(&self, name: &[u8]) -> Option<MaterialId> {
+        self.iter().find(|&(_, n, _)| n == name).map(|(id, _, _)| id)
+    }
+
+    pub fn get(&self, id: MaterialId) -> Option<&Paint> {
+        self.entries.get(id.0).and_then(|e| e.as_ref()).map(|e| &e.paint)
+    }
+
+    pub fn get_mut(&mut self, id: MaterialId) -> Option<&mut Paint> {
+        self.entries.get_mut(id.0).and_then(|e| e.as_mut()).map(|e| &mut e.paint)
+    }
+
+    pub fn name(&self, id: MaterialId) -> Option<&[u8]> {
+        self.entries.get(id.0).and_then(|e| e.as_ref()).map(|e| &e.name[..e.name_len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.iter().take_while(|e| e.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Entries with their ids and names.
+    pub fn iter(&self) -> impl Iterator<Item = (MaterialId, &[u8], &Paint)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.as_ref().map(|e| (i, e)))
+            .map(|(i, e)| (MaterialId(i), &e.name[..e.name_len], &e.paint))
+    }
+
+    /// Material of an entry at a surface point, missing entries show up magenta.
+    pub fn material(&self, id: MaterialId, ctx: &SurfaceContext) -> Material {
+        match self.get(id) {
+            Some(paint) => paint.material(ctx),
+            None => Material::Surface(Color::Magenta, Color::Magenta, Color::Magenta),
+        }
+    }
+}
+
+impl<const N: usize> Default for MaterialTable<N> {
+    fn default() -> MaterialTable<N> {
+        MaterialTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use v::v3;
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut t: MaterialTable<2> = MaterialTable::new();
+        assert!(t.is_empty());
+        let red = t.insert(b"red", Paint::Solid(Material::Finish(Finish::matte(Color::Red))));
+        let chrome = t.insert(b"chrome", Paint::Solid(Material::Mirror));
+        assert!(red == Some(MaterialId(0)));
+        assert!(chrome == Some(MaterialId(1)));
+        assert!(t.insert(b"blue", Paint::Solid(Material::Mirror)).is_none());
+        assert!(t.insert(b"much_too_long_name", Paint::Solid(Material::Mirror)).is_none());
+
+        // Redefining keeps the id.
+        assert!(t.insert(b"red", Paint::Solid(Material::Mirror)) == red);
+        assert!(t.len() == 2);
+        assert!(t.find(b"chrome") == chrome);
+        assert!(t.name(MaterialId(1)) == Some(&b"chrome"[..]));
+        match t.material(MaterialId(0), &SurfaceContext::at(v3(0, 0, 0))) {
+            Material::Mirror => {}
+            _ => panic!("Wrong material"),
+        }
+        match t.material(MaterialId(5), &SurfaceContext::at(v3(0, 0, 0))) {
+            Material::Surface(Color::Magenta, _, _) => {}
+            _ => panic!("Wrong material"),
+        }
+    }
+
+    #[test]
+    fn test_finish() {
+        let f = Finish {
+            albedo: Rgb::from(Color::Red),
+            reflectivity: fp(0),
+            specular: fp(1) / fp(2),
+            emission: fp(0),
+        };
+        assert!(f.shade(fp(1)).quantize() == Color::White);
+        assert!(f.shade(fp(1) / fp(2)).quantize() == Color::Red);
+        assert!(f.shade(fp(1) / fp(4)).quantize() == Color::Black);
+        assert!(f.shade(fp(0)) == Rgb::gray(fp(0)));
+
+        let glow = Finish { emission: fp(1), ..Finish::matte(Color::Green) };
+        assert!(glow.shade(fp(0)) == Rgb::from(Color::Green));
+    }
+}
@@ -0,0 +1,129 @@
+//! Deterministic fixed-point noise.
+//!
+//! Everything here is integer arithmetic, so the same seed gives the same bits on every target.
+//! The noise functions have a feature size of one unit, scale the position to change it.
+
+use fp::{fp, FP, DECIMAL_BITS};
+use v::V3;
+
+/// Hash a seed and a lattice point into 32 pseudorandom bits.
+pub fn hash(seed: u32, x: i32, y: i32, z: i32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x8da6_b343) ^
+                (y as u32).wrapping_mul(0xd816_3841) ^
+                (z as u32).wrapping_mul(0xcb1a_b31f);
+    // Murmur3 finalizer for avalanche.
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+/// Split a position into its lattice cell and the offset within the cell.
+fn cell(pos: &V3) -> ((i32, i32, i32), V3) {
+    const MASK: i32 = (1 << DECIMAL_BITS) - 1;
+    ((pos.x.to_i32(), pos.y.to_i32(), pos.z.to_i32()),
+     V3::new(FP(pos.x.0 & MASK), FP(pos.y.0 & MASK), FP(pos.z.0 & MASK)))
+}
+
+/// Quintic fade curve with zero first and second derivatives at the lattice points.
+fn fade(t: FP) -> FP {
+    t * t * t * (t * (t * fp(6) - fp(15)) + fp(10))
+}
+
+fn lerp(a: FP, b: FP, t: FP) -> FP {
+    a + (b - a) * t
+}
+
+/// Trilinear interpolation of lattice corner values with faded weights.
+fn interpolate<F>(pos: &V3, corner: F) -> FP
+    where F: Fn(i32, i32, i32, &V3) -> FP
+{
+    let ((x, y, z), f) = cell(pos);
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    let c = |dx, dy, dz| {
+        corner(x + dx, y + dy, z + dz, &(f - V3::new(fp(dx), fp(dy), fp(dz))))
+    };
+
+    lerp(lerp(lerp(c(0, 0, 0), c(1, 0, 0), u), lerp(c(0, 1, 0), c(1, 1, 0), u), v),
+         lerp(lerp(c(0, 0, 1), c(1, 0, 1), u), lerp(c(0, 1, 1), c(1, 1, 1), u), v),
+         w)
+}
+
+/// Value noise in [-1, 1].
+pub fn value_noise(seed: u32, pos: &V3) -> FP {
+    interpolate(pos, |x, y, z, _| {
+        // Top bits of the hash to [-1, 1).
+        FP((hash(seed, x, y, z) >> (31 - DECIMAL_BITS)) as i32 - fp(1).0)
+    })
+}
+
+/// Gradient noise in roughly [-1, 1], zero at the lattice points.
+pub fn gradient_noise(seed: u32, pos: &V3) -> FP {
+    interpolate(pos, |x, y, z, d| {
+        // Ken Perlin's twelve cube edge gradients, padded to sixteen.
+        match hash(seed, x, y, z) >> 28 {
+            0 | 12 => d.x + d.y,
+            1 | 13 => -d.x + d.y,
+            2 => d.x - d.y,
+            3 => -d.x - d.y,
+            4 => d.x + d.z,
+            5 => -d.x + d.z,
+            6 => d.x - d.z,
+            7 => -d.x - d.z,
+            8 => d.y + d.z,
+            9 | 14 => -d.y + d.z,
+            10 => d.y - d.z,
+            _ => -d.y - d.z,
+        }
+    })
+}
+
+/// Fractal Brownian motion, octaves of gradient noise at doubling frequency and halving amplitude.
+///
+/// The result stays in roughly [-1, 1] regardless of the octave count.
+pub fn fbm(seed: u32, pos: &V3, octaves: u32) -> FP {
+    let mut sum = fp(0);
+    let mut amplitude = fp(1) / fp(2);
+    let mut p = *pos;
+    for i in 0..octaves {
+        sum = sum + gradient_noise(seed.wrapping_add(i), &p) * amplitude;
+        amplitude = amplitude / fp(2);
+        p = p * fp(2);
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::{fp, FP};
+    use v::{v3, V3};
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        // Pin the bits so that any platform dependence shows up.
+        assert!(hash(0, 0, 0, 0) == 0);
+        assert!(hash(1, 2, 3, 4) == 0xe288_b75f);
+        assert!(hash(1, 2, 3, 4) != hash(2, 2, 3, 4));
+        assert!(hash(1, -2, 3, 4) != hash(1, 2, 3, 4));
+    }
+
+    #[test]
+    fn test_noise() {
+        let p = V3::new(FP(1234), FP(-567), FP(89));
+        assert!(value_noise(7, &p) == value_noise(7, &p));
+        assert!(value_noise(7, &p) != value_noise(8, &p));
+
+        // Gradient noise vanishes at lattice points.
+        assert!(gradient_noise(7, &v3(3, -4, 5)) == fp(0));
+
+        for i in 0..200 {
+            let p = V3::new(FP(i * 37), FP(i * -53), FP(i * 11));
+            for &n in &[value_noise(1, &p), gradient_noise(1, &p), fbm(1, &p, 4)] {
+                assert!(n >= fp(-1) && n <= fp(1));
+            }
+        }
+    }
+}
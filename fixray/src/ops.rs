@@ -0,0 +1,489 @@
+//! Domain operators that reshape the space a body is evaluated in.
+//!
+//! Each operator folds the query position into the domain of the wrapped body and evaluates the
+//! body once, so a hundred repeated pillars cost the same as one. The wrapped body should fit
+//! inside a single cell of the fold or the distances will be off near the cell boundaries.
+
+use fp::{fp, FP, TAU};
+use v::V3;
+use Material;
+use scene::{Body, ObjectId, SurfaceContext};
+
+/// Repetition of a body along the coordinate axes.
+pub struct Repeat<T> {
+    body: T,
+    period: V3,
+    first: [i32; 3],
+    last: [i32; 3],
+}
+
+impl<T: Body> Repeat<T> {
+    /// Repeat body infinitely with the given period along each axis.
+    ///
+    /// A zero period component leaves that axis unrepeated.
+    pub fn new(body: T, period: V3) -> Repeat<T> {
+        Repeat {
+            body,
+            period,
+            first: [i32::MIN; 3],
+            last: [i32::MAX; 3],
+        }
+    }
+
+    /// Repeat body a limited number of times along each axis.
+    ///
+    /// The original body is in the first cell and the copies are placed towards the positive end
+    /// of each axis.
+    pub fn finite(body: T, period: V3, count: [i32; 3]) -> Repeat<T> {
+        Repeat {
+            body,
+            period,
+            first: [0; 3],
+            last: [count[0] - 1, count[1] - 1, count[2] - 1],
+        }
+    }
+
+    fn fold(&self, pos: &V3) -> V3 {
+        fold_repeat(pos, &self.period, &self.first, &self.last)
+    }
+}
+
+impl<T: Body> Body for Repeat<T> {
+    fn distance(&self, pos: &V3) -> FP {
+        self.body.distance(&self.fold(pos))
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        self.body.material(&ctx.moved(self.fold(&ctx.pos)))
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.body.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        self.body.query(&self.fold(pos))
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        self.body.leaf_material(id, &ctx.moved(self.fold(&ctx.pos)))
+    }
+
+    /// Repetition only moves the body, so the normal stays the same.
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        self.body.leaf_normal(id, &self.fold(pos))
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        self.body.leaf_uv(id, &self.fold(pos))
+    }
+}
+
+/// Reflection of a body across a plane.
+///
+/// The space on the negative side of the plane becomes a mirror image of the positive side.
+pub struct Mirror<T> {
+    body: T,
+    normal: V3,
+    offset: FP,
+}
+
+impl<T: Body> Mirror<T> {
+    pub fn new(body: T, normal: V3, offset: FP) -> Mirror<T> {
+        Mirror {
+            body,
+            normal,
+            offset,
+        }
+    }
+
+    fn fold(&self, pos: &V3) -> V3 {
+        fold_mirror(pos, &self.normal, self.offset)
+    }
+}
+
+impl<T: Body> Body for Mirror<T> {
+    fn distance(&self, pos: &V3) -> FP {
+        self.body.distance(&self.fold(pos))
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        self.body.material(&ctx.moved(self.fold(&ctx.pos)))
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.body.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        self.body.query(&self.fold(pos))
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        self.body.leaf_material(id, &ctx.moved(self.fold(&ctx.pos)))
+    }
+
+    /// The normal of a reflected copy is reflected back.
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        let n = self.body.leaf_normal(id, &self.fold(pos));
+        if pos.dot(&self.normal) < self.offset {
+            n - self.normal * (fp(2) * n.dot(&self.normal))
+        } else {
+            n
+        }
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        self.body.leaf_uv(id, &self.fold(pos))
+    }
+}
+
+/// Radial repetition of a body around a vertical axis.
+///
+/// The body is modeled in the sector that faces the positive x axis from `center`, and the
+/// sector is repeated `count` times around the full circle.
+pub struct Polar<T> {
+    body: T,
+    center: V3,
+    sector: FP,
+}
+
+impl<T: Body> Polar<T> {
+    /// Return `None` if `count` is zero or too large for the sectors to have a fixed-point angle.
+    pub fn new(body: T, center: V3, count: u32) -> Option<Polar<T>> {
+        if count == 0 || count > TAU.0 as u32 {
+            return None;
+        }
+        Some(Polar {
+            body,
+            center,
+            sector: TAU / fp(count as i32),
+        })
+    }
+
+    fn fold(&self, pos: &V3) -> V3 {
+        fold_polar(pos, &self.center, self.sector)
+    }
+}
+
+impl<T: Body> Body for Polar<T> {
+    fn distance(&self, pos: &V3) -> FP {
+        self.body.distance(&self.fold(pos))
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        self.body.material(&ctx.moved(self.fold(&ctx.pos)))
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.body.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        self.body.query(&self.fold(pos))
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        self.body.leaf_material(id, &ctx.moved(self.fold(&ctx.pos)))
+    }
+
+    /// The normal of a copy is turned back around the axis with it.
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        let n = self.body.leaf_normal(id, &self.fold(pos));
+        let turn = polar_turn(pos, &self.center, self.sector);
+        let (sin, cos) = (turn.sin(), turn.cos());
+        V3::new(n.x * cos - n.y * sin, n.x * sin + n.y * cos, n.z)
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        self.body.leaf_uv(id, &self.fold(pos))
+    }
+}
+
+/// Fold a position into the cells of a repetition, limited to the cell range on each axis.
+pub(crate) fn fold_repeat(pos: &V3, period: &V3, first: &[i32; 3], last: &[i32; 3]) -> V3 {
+    fn fold_axis(x: FP, period: FP, first: i32, last: i32) -> FP {
+        if period <= fp(0) {
+            return x;
+        }
+        let cell = (x / period + fp(1) / fp(2)).to_i32();
+        let cell = if cell < first {
+            first
+        } else if cell > last {
+            last
+        } else {
+            cell
+        };
+        x - period * fp(cell)
+    }
+
+    V3::new(fold_axis(pos.x, period.x, first[0], last[0]),
+            fold_axis(pos.y, period.y, first[1], last[1]),
+            fold_axis(pos.z, period.z, first[2], last[2]))
+}
+
+/// Fold the negative side of a plane onto the positive side.
+pub(crate) fn fold_mirror(pos: &V3, normal: &V3, offset: FP) -> V3 {
+    let d = pos.dot(normal) - offset;
+    if d < fp(0) {
+        *pos - *normal * (fp(2) * d)
+    } else {
+        *pos
+    }
+}
+
+/// Rotate a position into the polar sector around the vertical axis through `center` that faces
+/// the positive x axis.
+pub(crate) fn fold_polar(pos: &V3, center: &V3, sector: FP) -> V3 {
+    let p = *pos - *center;
+    let angle = p.y.atan2(p.x) - polar_turn(pos, center, sector);
+    let r = (p.x * p.x + p.y * p.y).sqrt();
+    *center + V3::new(r * angle.cos(), r * angle.sin(), p.z)
+}
+
+/// Angle that `fold_polar` turns a position back by.
+fn polar_turn(pos: &V3, center: &V3, sector: FP) -> FP {
+    let p = *pos - *center;
+    let cell = (p.y.atan2(p.x) / sector + fp(1) / fp(2)).to_i32();
+    sector * fp(cell)
+}
+
+/// Body with a displacement function added to its surface distance.
+///
+/// Use a displacement like `noise::fbm` to roughen a surface. The displaced distance is no longer
+/// exact, so keep the displacement gradient small or scale it down to avoid overstepping the
+/// surface when marching.
+pub struct Displace<T, F> {
+    body: T,
+    displacement_f: F,
+}
+
+impl<T, F> Displace<T, F>
+    where T: Body,
+          F: Fn(&V3) -> FP
+{
+    pub fn new(body: T, displacement_f: F) -> Displace<T, F> {
+        Displace {
+            body,
+            displacement_f,
+        }
+    }
+}
+
+impl<T, F> Body for Displace<T, F>
+    where T: Body,
+          F: Fn(&V3) -> FP
+{
+    fn distance(&self, pos: &V3) -> FP {
+        self.body.distance(pos) + (self.displacement_f)(pos)
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        self.body.material(ctx)
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.body.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        let (d, id) = self.body.query(pos);
+        (d + (self.displacement_f)(pos), id)
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        self.body.leaf_material(id, ctx)
+    }
+
+    /// Tilt the normal of the leaf by the slope of the displacement.
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        let n = self.body.leaf_normal(id, pos);
+        let tilted = n + pos.slope(|p| (self.displacement_f)(&p));
+        if tilted.dot(&tilted) == fp(0) {
+            return n;
+        }
+        tilted.normalized()
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        self.body.leaf_uv(id, pos)
+    }
+}
+
+/// Body with its normals tilted by the slopes of a height function.
+///
+/// The surface stays where it is, so bumps cost no marching steps, but the outline and the
+/// shadows of the body stay smooth. `height_f` is scaled by `strength`, use a texture pattern or
+/// `ImageTexture::height_map` for fine detail like bricks or scratches.
+pub struct Bump<T, F> {
+    body: T,
+    height_f: F,
+    strength: FP,
+}
+
+impl<T, F> Bump<T, F>
+    where T: Body,
+          F: Fn(&V3) -> FP
+{
+    pub fn new(body: T, height_f: F, strength: FP) -> Bump<T, F> {
+        Bump {
+            body,
+            height_f,
+            strength,
+        }
+    }
+
+    /// Tilt a normal away from the slope of the height along the surface.
+    fn perturb(&self, pos: &V3, normal: V3) -> V3 {
+        let slope = pos.slope(|p| (self.height_f)(&p));
+        let along = slope - normal * slope.dot(&normal);
+        let bumped = normal - along * self.strength;
+        // Nothing to tilt, like the zero normal of a flat field.
+        if bumped.dot(&bumped) == fp(0) {
+            return normal;
+        }
+        bumped.normalized()
+    }
+}
+
+impl<T, F> Body for Bump<T, F>
+    where T: Body,
+          F: Fn(&V3) -> FP
+{
+    fn distance(&self, pos: &V3) -> FP {
+        self.body.distance(pos)
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        self.body.material(ctx)
+    }
+
+    fn normal(&self, pos: &V3) -> V3 {
+        self.perturb(pos, self.body.normal(pos))
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.body.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        self.body.query(pos)
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        self.body.leaf_material(id, ctx)
+    }
+
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        self.perturb(pos, self.body.leaf_normal(id, pos))
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        self.body.leaf_uv(id, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::{fp, FP};
+    use v::{v3, V3};
+    use scene::{Body, Object};
+    use shape::{sphere_fn, box_fn, plane_fn};
+    use {m, Material};
+    use super::*;
+
+    fn ball(center: V3) -> impl Body {
+        Object::new(sphere_fn(center, fp(1)), m(Material::Mirror))
+    }
+
+    /// Ball with surface coordinates that show where it was evaluated.
+    fn tagged(center: V3) -> impl Body {
+        Object::new(sphere_fn(center, fp(1)), m(Material::Mirror)).with_uv(|p: &V3| (fp(7), p.x))
+    }
+
+    #[test]
+    fn test_repeat() {
+        let b = Repeat::new(ball(v3(0, 0, 0)), v3(4, 0, 0));
+        assert!(b.distance(&v3(0, 0, 0)) == fp(-1));
+        assert!(b.distance(&v3(40, 0, 0)) == fp(-1));
+        assert!(b.distance(&v3(-8, 0, 0)) == fp(-1));
+        assert!(b.distance(&v3(2, 0, 0)) == fp(1));
+        assert!(b.distance(&v3(40, 0, 3)) == fp(2));
+
+        let b = Repeat::finite(ball(v3(0, 0, 0)), v3(4, 4, 0), [3, 1, 1]);
+        assert!(b.distance(&v3(8, 0, 0)) == fp(-1));
+        assert!(b.distance(&v3(12, 0, 0)) == fp(3));
+        assert!(b.distance(&v3(-4, 0, 0)) == fp(3));
+        assert!(b.distance(&v3(4, 4, 0)) == fp(3));
+    }
+
+    #[test]
+    fn test_mirror() {
+        let b = Mirror::new(ball(v3(3, 0, 0)), v3(1, 0, 0), fp(1));
+        assert!(b.distance(&v3(3, 0, 0)) == fp(-1));
+        assert!(b.distance(&v3(-1, 0, 0)) == fp(-1));
+        assert!(b.distance(&v3(1, 0, 0)) == fp(1));
+    }
+
+    #[test]
+    fn test_polar() {
+        let b = Polar::new(Object::new(box_fn(v3(4, 0, 0), v3(1, 1, 1)), m(Material::Mirror)),
+                           v3(0, 0, 0),
+                           4)
+            .unwrap();
+        for &p in &[v3(4, 0, 0), v3(0, 4, 0), v3(-4, 0, 0), v3(0, -4, 0)] {
+            assert!((b.distance(&p) - fp(-1)).abs() <= FP(4));
+        }
+        assert!(b.distance(&v3(3, 3, 0)) > fp(1));
+        assert!(b.distance(&v3(0, 0, 0)) == fp(3));
+
+        assert!(Polar::new(ball(v3(3, 0, 0)), v3(0, 0, 0), 0).is_none());
+        assert!(Polar::new(ball(v3(3, 0, 0)), v3(0, 0, 0), 5000).is_none());
+    }
+
+    #[test]
+    fn test_leaves() {
+        let near = |a: V3, b: V3| (a - b).abs() <= FP(8);
+
+        let b = Repeat::new(tagged(v3(0, 0, 0)), v3(4, 0, 0));
+        assert!(b.leaf_count() == 1 && b.query(&v3(41, 0, 0)) == (fp(0), ObjectId(0)));
+        assert!(b.leaf_uv(ObjectId(0), &v3(41, 0, 0)) == (fp(7), fp(1)));
+        assert!(near(b.leaf_normal(ObjectId(0), &v3(41, 0, 0)), v3(1, 0, 0)));
+
+        let b = Mirror::new(tagged(v3(3, 0, 0)), v3(1, 0, 0), fp(1));
+        assert!(b.query(&v3(-2, 0, 0)) == (fp(0), ObjectId(0)));
+        assert!(b.leaf_uv(ObjectId(0), &v3(-2, 0, 0)) == (fp(7), fp(4)));
+        assert!(near(b.leaf_normal(ObjectId(0), &v3(-2, 0, 0)), v3(-1, 0, 0)));
+
+        let b = Polar::new(tagged(v3(4, 0, 0)), v3(0, 0, 0), 4).unwrap();
+        let (u, v) = b.leaf_uv(ObjectId(0), &v3(0, 5, 0));
+        assert!(u == fp(7) && (v - fp(5)).abs() <= FP(8));
+        assert!(near(b.leaf_normal(ObjectId(0), &v3(0, 5, 0)), v3(0, 1, 0)));
+        assert!(near(b.leaf_normal(ObjectId(0), &v3(0, -5, 0)), v3(0, -1, 0)));
+
+        let floor = Object::new(plane_fn(v3(0, 0, 1), fp(0)), m(Material::Mirror))
+            .with_uv(|p: &V3| (fp(7), p.x));
+        let b = Displace::new(floor, |p: &V3| p.x / fp(4));
+        assert!(b.query(&v3(4, 0, 0)) == (fp(1), ObjectId(0)));
+        assert!(b.leaf_uv(ObjectId(0), &v3(4, 0, 0)) == (fp(7), fp(4)));
+        let n = b.leaf_normal(ObjectId(0), &v3(4, 0, 0));
+        assert!(near(n, V3::new(fp(1), fp(0), fp(4)).normalized()));
+    }
+
+    #[test]
+    fn test_bump() {
+        let floor = || Object::new(plane_fn(v3(0, 0, 1), fp(0)), m(Material::Mirror));
+        // Rising a quarter unit per unit along x.
+        let ramp = Bump::new(floor(), |p: &V3| p.x / fp(4), fp(1));
+        assert!(ramp.distance(&v3(5, 3, 1)) == fp(1));
+        let n = ramp.normal(&v3(5, 3, 0));
+        assert!((n - V3::new(fp(-1), fp(0), fp(4)).normalized()).abs() <= FP(4));
+        assert!(ramp.leaf_normal(ObjectId(0), &v3(5, 3, 0)) == n);
+
+        // Only the slope along the surface tilts the normal.
+        let steps = Bump::new(floor(), |p: &V3| p.z, fp(1));
+        assert!(steps.normal(&v3(5, 3, 0)) == v3(0, 0, 1));
+        let flat = Bump::new(floor(), |p: &V3| p.x, fp(0));
+        assert!(flat.normal(&v3(5, 3, 0)) == v3(0, 0, 1));
+    }
+}
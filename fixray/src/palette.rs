@@ -0,0 +1,234 @@
+//! Fixed sets of colors that a display can show.
+
+use fp::{fp, FP};
+use rgb::Rgb;
+use Color;
+
+pub trait Palette {
+    /// Number of colors in the palette.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Color of a palette entry.
+    fn color(&self, index: usize) -> Rgb;
+
+    /// Index of the entry closest to a color.
+    ///
+    /// The default searches through all the entries, palettes with a regular layout can find the
+    /// entry directly.
+    fn nearest(&self, rgb: Rgb) -> usize {
+        let mut best = (i32::MAX, 0);
+        for i in 0..self.len() {
+            let d = distance(rgb, self.color(i));
+            if d < best.0 {
+                best = (d, i);
+            }
+        }
+        best.1
+    }
+}
+
+/// Squared distance between colors in raw fixed-point units.
+///
+/// Both colors are clamped first, so overbright shading can't overflow the sum.
+fn distance(a: Rgb, b: Rgb) -> i32 {
+    let d = a.clamp() - b.clamp();
+    let sq = |c: FP| (c.0 >> 2) * (c.0 >> 2);
+    sq(d.r) + sq(d.g) + sq(d.b)
+}
+
+/// The 8 basic ANSI colors, indexed like `Color`.
+pub struct Ansi8;
+
+impl Palette for Ansi8 {
+    fn len(&self) -> usize {
+        8
+    }
+
+    fn color(&self, index: usize) -> Rgb {
+        Rgb::from(Color::from_bits(index as u8))
+    }
+
+    fn nearest(&self, rgb: Rgb) -> usize {
+        rgb.quantize() as usize
+    }
+}
+
+/// The 8 ANSI colors at two thirds intensity followed by their bright versions.
+pub struct Ansi16;
+
+impl Palette for Ansi16 {
+    fn len(&self) -> usize {
+        16
+    }
+
+    fn color(&self, index: usize) -> Rgb {
+        let (base, level) = if index < 8 {
+            (fp(0), fp(2) / fp(3))
+        } else {
+            (fp(1) / fp(3), fp(2) / fp(3))
+        };
+        Rgb::gray(base) + Rgb::from(Color::from_bits(index as u8)) * level
+    }
+}
+
+/// The xterm 256 color palette: the 16 ANSI colors, a 6x6x6 color cube and 24 grays.
+pub struct Xterm256;
+
+/// Channel levels of the xterm color cube.
+const CUBE_LEVELS: [i32; 6] = [0, 95, 135, 175, 215, 255];
+
+fn byte(b: i32) -> FP {
+    FP(b * 256 / 255)
+}
+
+impl Palette for Xterm256 {
+    fn len(&self) -> usize {
+        256
+    }
+
+    fn color(&self, index: usize) -> Rgb {
+        match index {
+            0..=15 => Ansi16.color(index),
+            16..=231 => {
+                let i = index - 16;
+                Rgb::new(byte(CUBE_LEVELS[i / 36]),
+                         byte(CUBE_LEVELS[i / 6 % 6]),
+                         byte(CUBE_LEVELS[i % 6]))
+            }
+            _ => Rgb::gray(byte(8 + 10 * (index as i32 - 232))),
+        }
+    }
+
+    /// Pick between the closest cube and gray entries, the ANSI colors are left alone since
+    /// terminals often change them.
+    fn nearest(&self, rgb: Rgb) -> usize {
+        let level = |c: FP| {
+            let b = (c.clamp(fp(0), fp(1)) * fp(255)).to_i32();
+            let mut i = 0;
+            while i < 5 && b > (CUBE_LEVELS[i] + CUBE_LEVELS[i + 1]) / 2 {
+                i += 1;
+            }
+            i
+        };
+        let cube = 16 + 36 * level(rgb.r) + 6 * level(rgb.g) + level(rgb.b);
+
+        let avg = (rgb.r + rgb.g + rgb.b) / fp(3);
+        let step = ((avg.clamp(fp(0), fp(1)) * fp(255)).to_i32() - 3) / 10;
+        let gray = 232 + step.clamp(0, 23) as usize;
+
+        if distance(rgb, self.color(gray)) < distance(rgb, self.color(cube)) {
+            gray
+        } else {
+            cube
+        }
+    }
+}
+
+/// Even ramp of grays from black to white.
+pub struct Grayscale {
+    levels: usize,
+}
+
+impl Grayscale {
+    /// Ramp of `levels` grays.
+    ///
+    /// Return `None` if there are fewer than 2 levels, which can't reach both black and white.
+    pub fn new(levels: usize) -> Option<Grayscale> {
+        if levels < 2 {
+            return None;
+        }
+        Some(Grayscale { levels })
+    }
+}
+
+impl Palette for Grayscale {
+    fn len(&self) -> usize {
+        self.levels
+    }
+
+    fn color(&self, index: usize) -> Rgb {
+        Rgb::gray(fp(index as i32) / fp(self.levels as i32 - 1))
+    }
+
+    fn nearest(&self, rgb: Rgb) -> usize {
+        let y = rgb.luminance();
+        let top = self.levels as i32 - 1;
+        let i = (y * fp(top) + fp(1) / fp(2)).to_i32();
+        i.clamp(0, top) as usize
+    }
+}
+
+/// Palette with arbitrary colors.
+pub struct Table<'a>(&'a [Rgb]);
+
+impl<'a> Table<'a> {
+    /// Return `None` if there are no colors to pick from.
+    pub fn new(colors: &'a [Rgb]) -> Option<Table<'a>> {
+        if colors.is_empty() {
+            return None;
+        }
+        Some(Table(colors))
+    }
+}
+
+impl<'a> Palette for Table<'a> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn color(&self, index: usize) -> Rgb {
+        self.0[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest() {
+        let orange = Rgb::new(fp(1), fp(1) / fp(2), fp(0));
+        let palettes: [(&dyn Palette, usize); 4] =
+            [(&Ansi8, 0), (&Ansi16, 0), (&Xterm256, 16), (&Grayscale::new(4).unwrap(), 0)];
+        for &(p, first) in &palettes {
+            // The entries map back to themselves, except the ANSI colors of xterm that aren't
+            // searched.
+            for i in first..p.len() {
+                let c = p.color(i);
+                assert!(distance(c, p.color(p.nearest(c))) == 0);
+            }
+            // The shortcuts agree with searching the whole palette.
+            let mut best = (i32::MAX, 0);
+            for i in 0..p.len() {
+                best = best.min((distance(orange, p.color(i)), i));
+            }
+            assert!(distance(orange, p.color(p.nearest(orange))) <= best.0 * 2);
+        }
+
+        assert!(Ansi8.nearest(orange) == Color::Yellow as usize);
+        assert!(Ansi16.nearest(Rgb::from(Color::White)) == 15);
+        assert!(Ansi16.nearest(Rgb::from(Color::Blue)) == 4);
+        assert!(Xterm256.nearest(Rgb::gray(fp(1) / fp(2))) == 244);
+        assert!(Grayscale::new(4).unwrap().nearest(Rgb::from(Color::White)) == 3);
+        let black_white = Grayscale::new(2).unwrap();
+        assert!(black_white.nearest(Rgb::gray(fp(1) / fp(4))) == 0);
+        assert!(black_white.color(1) == Rgb::gray(fp(1)));
+        assert!(Grayscale::new(1).is_none() && Grayscale::new(0).is_none());
+
+        let table = [Rgb::from(Color::Red), Rgb::from(Color::Cyan)];
+        assert!(Table::new(&table).unwrap().nearest(Rgb::from(Color::Blue)) == 1);
+        assert!(Table::new(&[]).is_none());
+
+        // Colors far outside the displayable range pick the closest edge.
+        let bright = Rgb::gray(fp(1000));
+        assert!(Ansi16.nearest(bright) == 15);
+        assert!(Xterm256.nearest(bright) == 231);
+        let grays = [Rgb::gray(fp(0)), Rgb::gray(fp(1) / fp(2)), Rgb::gray(fp(100))];
+        assert!(Table::new(&grays).unwrap().nearest(bright) == 2);
+        assert!(Table::new(&grays).unwrap().nearest(Rgb::gray(fp(-1000))) == 0);
+    }
+}
@@ -0,0 +1,138 @@
+//! Linear RGB colors for shading.
+
+use core::ops::{Add, Sub, Mul};
+
+use fp::{fp, FP};
+use Color;
+
+/// Color with linear channels between 0 and 1.
+///
+/// Shading happens in `Rgb` and the result is quantized to the palette of the display at the
+/// end, so blends and dim lights aren't rounded off at every step.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Rgb {
+    pub r: FP,
+    pub g: FP,
+    pub b: FP,
+}
+
+impl Rgb {
+    pub fn new(r: FP, g: FP, b: FP) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    pub fn gray(v: FP) -> Rgb {
+        Rgb::new(v, v, v)
+    }
+
+    /// Blend with `amount` of another color.
+    pub fn mix(self, other: Rgb, amount: FP) -> Rgb {
+        self * (fp(1) - amount) + other * amount
+    }
+
+    /// Clamp the channels to the displayable range.
+    pub fn clamp(self) -> Rgb {
+        Rgb::new(self.r.clamp(fp(0), fp(1)),
+                 self.g.clamp(fp(0), fp(1)),
+                 self.b.clamp(fp(0), fp(1)))
+    }
+
+    /// Nearest color of the 3-bit palette.
+    pub fn quantize(self) -> Color {
+        let half = fp(1) / fp(2);
+        let bit = |c: FP, b: u8| if c >= half { b } else { 0 };
+        Color::from_bits(bit(self.r, 1) | bit(self.g, 2) | bit(self.b, 4))
+    }
+
+    /// Brightness as the eye sees it, by Rec. 709.
+    pub fn luminance(self) -> FP {
+        self.r * FP(54) + self.g * FP(183) + self.b * FP(19)
+    }
+
+    /// Color from bytes of 8 bit channels.
+    pub fn from_bytes(bytes: [u8; 3]) -> Rgb {
+        let channel = |b: u8| FP(b as i32 * 256 / 255);
+        Rgb::new(channel(bytes[0]), channel(bytes[1]), channel(bytes[2]))
+    }
+
+    /// Channels as bytes, for displays with 8 bits per channel.
+    pub fn to_bytes(self) -> [u8; 3] {
+        let byte = |c: FP| (c.clamp(fp(0), fp(1)) * fp(255)).to_i32() as u8;
+        [byte(self.r), byte(self.g), byte(self.b)]
+    }
+}
+
+impl From<Color> for Rgb {
+    fn from(c: Color) -> Rgb {
+        let bits = c as u8;
+        let channel = |b: u8| if bits & b != 0 { fp(1) } else { fp(0) };
+        Rgb::new(channel(1), channel(2), channel(4))
+    }
+}
+
+impl Add for Rgb {
+    type Output = Rgb;
+
+    fn add(self, other: Rgb) -> Rgb {
+        Rgb::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl Sub for Rgb {
+    type Output = Rgb;
+
+    fn sub(self, other: Rgb) -> Rgb {
+        Rgb::new(self.r - other.r, self.g - other.g, self.b - other.b)
+    }
+}
+
+impl Mul<FP> for Rgb {
+    type Output = Rgb;
+
+    fn mul(self, k: FP) -> Rgb {
+        Rgb::new(self.r * k, self.g * k, self.b * k)
+    }
+}
+
+/// Filter one color through another.
+impl Mul<Rgb> for Rgb {
+    type Output = Rgb;
+
+    fn mul(self, other: Rgb) -> Rgb {
+        Rgb::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize() {
+        let colors = [Color::Black,
+                      Color::Red,
+                      Color::Green,
+                      Color::Yellow,
+                      Color::Blue,
+                      Color::Magenta,
+                      Color::Cyan,
+                      Color::White];
+        for &c in &colors {
+            assert!(Rgb::from(c).quantize() == c);
+        }
+        let dim_orange = Rgb::new(fp(3) / fp(4), fp(1) / fp(2), fp(1) / fp(8));
+        assert!(dim_orange.quantize() == Color::Yellow);
+        assert!(Rgb::gray(fp(1) / fp(4)).quantize() == Color::Black);
+
+        let red = Rgb::from(Color::Red);
+        let blue = Rgb::from(Color::Blue);
+        assert!(red.mix(blue, fp(1) / fp(4)).quantize() == Color::Red);
+        assert!(red.mix(blue, fp(1) / fp(2)).quantize() == Color::Magenta);
+        assert!((Rgb::from(Color::Yellow) * Rgb::from(Color::Cyan)).quantize() == Color::Green);
+        assert!((red * fp(2)).clamp() == red);
+        assert!(Rgb::gray(fp(1) / fp(2)).to_bytes() == [127, 127, 127]);
+        assert!(Rgb::from_bytes([255, 0, 255]) == Rgb::from(Color::Magenta));
+        assert!(Rgb::from(Color::White).luminance() == fp(1));
+        assert!(Rgb::from(Color::Green).luminance() > Rgb::from(Color::Magenta).luminance());
+    }
+}
@@ -2,17 +2,63 @@ use core::ops::Add;
 use core::cmp::min;
 
 pub use fp::FP;
+use fp::fp;
 pub use v::V3;
+use v::v3;
+use geom::{Frustum, Transform};
+use group::Group;
+use light::{Light, Lights, MAX_LIGHTS};
+use material::{MaterialId, MaterialTable, MAX_MATERIALS};
+use shape::Shape;
+use uv;
 use {Color, Material};
 
+/// Index of a leaf body inside a composite body.
+///
+/// Leaves are numbered from zero in the order they were added.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ObjectId(pub usize);
+
+/// What a material function knows about the surface point it shades.
+#[derive(Copy, Clone)]
+pub struct SurfaceContext {
+    pub pos: V3,
+    /// Unit normal on the side the ray came from.
+    pub normal: V3,
+    /// Unit direction of the ray arriving at the surface.
+    pub dir: V3,
+    /// Surface coordinates from `Body::leaf_uv`.
+    pub uv: (FP, FP),
+    /// Leaf of the scene being shaded.
+    pub id: ObjectId,
+}
+
+impl SurfaceContext {
+    /// Context for a bare position, with no normal or view direction.
+    pub fn at(pos: V3) -> SurfaceContext {
+        SurfaceContext {
+            pos,
+            normal: v3(0, 0, 0),
+            dir: v3(0, 0, 0),
+            uv: (pos.x, pos.y),
+            id: ObjectId(0),
+        }
+    }
+
+    /// The same surface point seen at another position, for bodies that move their children.
+    pub fn moved(&self, pos: V3) -> SurfaceContext {
+        SurfaceContext { pos, ..*self }
+    }
+}
+
 /// Signed distance field object.
 pub trait Body {
     /// Signed distance function for the surface of the body.
     fn distance(&self, pos: &V3) -> FP;
 
-    /// Material of the body at position.
-    fn material(&self, pos: &V3) -> Material {
-        let _ = pos;
+    /// Material of the body at a surface point.
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        let _ = ctx;
         Material::Surface(Color::Yellow, Color::Green, Color::Blue)
     }
 
@@ -21,6 +67,70 @@ pub trait Body {
         let _ = pos;
         pos.grad(|p| self.distance(&p))
     }
+
+    /// Number of leaf bodies this body is made of.
+    fn leaf_count(&self) -> usize {
+        1
+    }
+
+    /// Distance to the body and the leaf that is closest to the position.
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        (self.distance(pos), ObjectId(0))
+    }
+
+    /// Material of a specific leaf at a surface point.
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        let _ = id;
+        self.material(ctx)
+    }
+
+    /// Normal of a specific leaf at position.
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        let _ = id;
+        self.normal(pos)
+    }
+
+    /// Surface coordinates of a specific leaf at position.
+    ///
+    /// The default projects the position on the xy plane.
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        let _ = id;
+        uv::planar(pos)
+    }
+}
+
+impl<T: Body + ?Sized> Body for &T {
+    fn distance(&self, pos: &V3) -> FP {
+        (**self).distance(pos)
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        (**self).material(ctx)
+    }
+
+    fn normal(&self, pos: &V3) -> V3 {
+        (**self).normal(pos)
+    }
+
+    fn leaf_count(&self) -> usize {
+        (**self).leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        (**self).query(pos)
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        (**self).leaf_material(id, ctx)
+    }
+
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        (**self).leaf_normal(id, pos)
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        (**self).leaf_uv(id, pos)
+    }
 }
 
 /// A combination of several bodies.
@@ -36,21 +146,56 @@ impl<T: Body, U: Body> Body for Union<T, U> {
         min(self.first.distance(pos), self.second.distance(pos))
     }
 
-    fn material(&self, pos: &V3) -> Material {
-        if self.first.distance(pos) < self.second.distance(pos) {
-            self.first.material(pos)
-        } else {
-            self.second.material(pos)
-        }
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        let (_, id) = self.query(&ctx.pos);
+        self.leaf_material(id, ctx)
     }
 
     fn normal(&self, pos: &V3) -> V3 {
         // Overriding this so that it'll fall into the cheaper component method before starting the
         // expensive gradient operation.
-        if self.first.distance(pos) < self.second.distance(pos) {
-            self.first.normal(pos)
+        let (_, id) = self.query(pos);
+        self.leaf_normal(id, pos)
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.first.leaf_count() + self.second.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        let (d1, id1) = self.first.query(pos);
+        let (d2, id2) = self.second.query(pos);
+        if d1 < d2 {
+            (d1, id1)
+        } else {
+            (d2, ObjectId(self.first.leaf_count() + id2.0))
+        }
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        let n = self.first.leaf_count();
+        if id.0 < n {
+            self.first.leaf_material(id, ctx)
         } else {
-            self.second.normal(pos)
+            self.second.leaf_material(ObjectId(id.0 - n), ctx)
+        }
+    }
+
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        let n = self.first.leaf_count();
+        if id.0 < n {
+            self.first.leaf_normal(id, pos)
+        } else {
+            self.second.leaf_normal(ObjectId(id.0 - n), pos)
+        }
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        let n = self.first.leaf_count();
+        if id.0 < n {
+            self.first.leaf_uv(id, pos)
+        } else {
+            self.second.leaf_uv(ObjectId(id.0 - n), pos)
         }
     }
 }
@@ -67,16 +212,18 @@ impl<T: Body, U: Body, V: Body> Add<V> for Union<T, U> {
 }
 
 /// Starter object for a scene.
+///
+/// The starter is the first leaf of the scene, so the objects added to it are numbered from one.
 pub struct Scene;
 
 impl Body for Scene {
     fn distance(&self, pos: &V3) -> FP {
         let _ = pos;
-        FP(i32::max_value())
+        FP(i32::MAX)
     }
 
-    fn material(&self, pos: &V3) -> Material {
-        let _ = pos;
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        let _ = ctx;
         Material::Surface(Color::Cyan, Color::Cyan, Color::Cyan)
     }
 }
@@ -92,40 +239,288 @@ impl<T: Body> Add<T> for Scene {
     }
 }
 
-pub struct Object<F, G> {
+/// Body from a distance function and a material function.
+///
+/// Wrap material functions of position only, like the `texture` ones, with `at_pos`. The surface
+/// coordinates are the xy plane unless given a mapping with `with_uv`.
+pub struct Object<F, G, U = fn(&V3) -> (FP, FP)> {
     distance_f: F,
     material_f: G,
+    uv_f: U,
 }
 
 impl<F, G> Object<F, G>
     where F: Fn(&V3) -> FP,
-          G: Fn(&V3) -> Material
+          G: Fn(&SurfaceContext) -> Material
 {
     pub fn new(distance_f: F, material_f: G) -> Object<F, G> {
         Object {
-            distance_f: distance_f,
-            material_f: material_f,
+            distance_f,
+            material_f,
+            uv_f: uv::planar,
+        }
+    }
+}
+
+impl<F, G, U> Object<F, G, U>
+    where F: Fn(&V3) -> FP,
+          G: Fn(&SurfaceContext) -> Material,
+          U: Fn(&V3) -> (FP, FP)
+{
+    /// Same object with surface coordinates from `uv_f`.
+    pub fn with_uv<V>(self, uv_f: V) -> Object<F, G, V>
+        where V: Fn(&V3) -> (FP, FP)
+    {
+        Object {
+            distance_f: self.distance_f,
+            material_f: self.material_f,
+            uv_f,
         }
     }
 }
 
-impl<F, G> Body for Object<F, G>
+impl<F, G, U> Body for Object<F, G, U>
     where F: Fn(&V3) -> FP,
-          G: Fn(&V3) -> Material
+          G: Fn(&SurfaceContext) -> Material,
+          U: Fn(&V3) -> (FP, FP)
 {
     fn distance(&self, pos: &V3) -> FP {
         (self.distance_f)(pos)
     }
 
-    fn material(&self, pos: &V3) -> Material {
-        (self.material_f)(pos)
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        (self.material_f)(ctx)
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        let _ = id;
+        (self.uv_f)(pos)
+    }
+}
+
+/// Object made of plain data that can be built and changed at runtime.
+///
+/// The material is looked up from the `MaterialTable` of the scene the object is in.
+#[derive(Copy, Clone)]
+pub struct Primitive {
+    pub shape: Shape,
+    pub material: MaterialId,
+    pub transform: Transform,
+}
+
+impl Primitive {
+    pub fn new(shape: Shape, material: MaterialId) -> Primitive {
+        Primitive {
+            shape,
+            material,
+            transform: Transform::identity(),
+        }
+    }
+
+    pub fn transformed(self, transform: Transform) -> Primitive {
+        Primitive { transform, ..self }
+    }
+}
+
+impl Body for Primitive {
+    fn distance(&self, pos: &V3) -> FP {
+        self.transform.to_world_distance(self.shape.distance(&self.transform.to_local(pos)))
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        let _ = id;
+        self.shape.uv(&self.transform.to_local(pos))
     }
 }
 
-pub fn sphere_fn(center: V3, radius: FP) -> impl Fn(&V3) -> FP {
-    move |&p| (p - center).abs() - radius
+/// Complete scene loaded from data, with room for `N` objects, `M` materials and `L` lights.
+///
+/// The description is a body that shades its objects with the materials from the table.
+pub struct Description<const N: usize,
+                       const M: usize = MAX_MATERIALS,
+                       const L: usize = MAX_LIGHTS> {
+    pub objects: Group<Primitive, N>,
+    pub materials: MaterialTable<M>,
+    pub camera: Frustum,
+    pub lights: Lights<L>,
+}
+
+impl<const N: usize, const M: usize, const L: usize> Description<N, M, L> {
+    /// Empty scene without lights looking along the x axis.
+    pub fn new() -> Description<N, M, L> {
+        Description {
+            objects: Group::new(),
+            materials: MaterialTable::new(),
+            camera: Frustum {
+                origin: v3(0, 0, 0),
+                dir: v3(1, 0, 0),
+                up: v3(0, 0, 1),
+            },
+            lights: Lights::new(),
+        }
+    }
+
+    /// Light a scene that has no lights from straight above, so that loaded scenes aren't dark.
+    pub fn add_default_light(&mut self) {
+        if self.lights.is_empty() {
+            let light = Light::Directional {
+                dir: v3(0, 0, -1),
+                intensity: fp(1),
+            };
+            self.lights.push(light).ok();
+        }
+    }
 }
 
-pub fn plane_fn(normal: V3, offset: FP) -> impl Fn(&V3) -> FP {
-    move |&p| p.dot(&normal) - offset
+impl<const N: usize, const M: usize, const L: usize> Default for Description<N, M, L> {
+    fn default() -> Description<N, M, L> {
+        Description::new()
+    }
+}
+
+impl<const N: usize, const M: usize, const L: usize> Body for Description<N, M, L> {
+    fn distance(&self, pos: &V3) -> FP {
+        self.objects.distance(pos)
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        let (_, id) = self.query(&ctx.pos);
+        self.leaf_material(id, ctx)
+    }
+
+    fn normal(&self, pos: &V3) -> V3 {
+        self.objects.normal(pos)
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.objects.leaf_count()
+    }
+
+    fn query(&self, pos: &V3) -> (FP, ObjectId) {
+        self.objects.query(pos)
+    }
+
+    fn leaf_material(&self, id: ObjectId, ctx: &SurfaceContext) -> Material {
+        match self.objects.leaf(id) {
+            Some((p, _)) => {
+                let local = ctx.moved(p.transform.to_local(&ctx.pos));
+                self.materials.material(p.material, &local)
+            }
+            None => Material::Surface(Color::Cyan, Color::Cyan, Color::Cyan),
+        }
+    }
+
+    fn leaf_normal(&self, id: ObjectId, pos: &V3) -> V3 {
+        self.objects.leaf_normal(id, pos)
+    }
+
+    fn leaf_uv(&self, id: ObjectId, pos: &V3) -> (FP, FP) {
+        self.objects.leaf_uv(id, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use fp::fp;
+    use v::v3;
+    use material::Finish;
+    use rgb::Rgb;
+    use shape::{sphere_fn, Shape};
+    use {m, Color, Material, Paint};
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let evals = Cell::new(0);
+        let ball = |x, col| {
+            let f = sphere_fn(v3(x, 0, 0), fp(1));
+            let evals = &evals;
+            Object::new(move |p: &V3| {
+                            evals.set(evals.get() + 1);
+                            f(p)
+                        },
+                        m(Material::Surface(col, col, col)))
+        };
+        let scene = Scene + ball(0, Color::Red) + ball(10, Color::Green) +
+                    ball(20, Color::Blue) + ball(30, Color::White);
+        assert!(scene.leaf_count() == 5);
+
+        let (d, id) = scene.query(&v3(21, 0, 0));
+        assert!(d == fp(0));
+        assert!(id == ObjectId(3));
+        // One pass over the objects.
+        assert!(evals.get() == 4);
+
+        match scene.leaf_material(id, &SurfaceContext::at(v3(21, 0, 0))) {
+            Material::Surface(Color::Blue, _, _) => {}
+            _ => panic!("Wrong material"),
+        }
+        assert!(scene.query(&v3(-5, 0, 0)).1 == ObjectId(1));
+        assert!(scene.query(&v3(29, 0, 0)).1 == ObjectId(4));
+    }
+
+    #[test]
+    fn test_description_materials() {
+        let mut desc: Description<2, 2> = Description::new();
+        let red = desc.materials
+            .insert(b"red", Paint::Solid(Material::Finish(Finish::matte(Color::Red))))
+            .unwrap();
+        let ball = |x| Shape::Sphere { center: v3(x, 0, 0), radius: fp(1) };
+        desc.objects.insert(Primitive::new(ball(0), red)).ok();
+        desc.objects.insert(Primitive::new(ball(10), MaterialId(1))).ok();
+
+        match desc.material(&SurfaceContext::at(v3(1, 0, 0))) {
+            Material::Finish(f) => assert!(f.albedo == Rgb::from(Color::Red)),
+            _ => panic!("Wrong material"),
+        }
+        // Undefined materials are flagged.
+        match desc.material(&SurfaceContext::at(v3(9, 0, 0))) {
+            Material::Surface(Color::Magenta, _, _) => {}
+            _ => panic!("Wrong material"),
+        }
+
+        // Editing the table changes every object that uses the material.
+        *desc.materials.get_mut(red).unwrap() = Paint::Solid(Material::Mirror);
+        match desc.material(&SurfaceContext::at(v3(1, 0, 0))) {
+            Material::Mirror => {}
+            _ => panic!("Wrong material"),
+        }
+
+        // Patterns are laid out in the object's own frame.
+        let white = Material::Finish(Finish::matte(Color::White));
+        let mirror = Material::Mirror;
+        desc.materials.insert(b"checks", Paint::Checkerboard(white, mirror)).unwrap();
+        let moved = Transform::identity().translate(v3(8, 0, 0));
+        let ball = Shape::Sphere { center: v3(2, 2, 2), radius: fp(1) };
+        *desc.objects.get_mut(1).unwrap() = Primitive::new(ball, MaterialId(1)).transformed(moved);
+        let local = desc.materials.material(MaterialId(1), &SurfaceContext::at(v3(2, 2, 3)));
+        match (local, desc.material(&SurfaceContext::at(v3(10, 2, 3)))) {
+            (Material::Mirror, Material::Mirror) |
+            (Material::Finish(_), Material::Finish(_)) => {}
+            _ => panic!("Wrong material"),
+        }
+    }
+
+    #[test]
+    fn test_uv() {
+        let half = fp(1) / fp(2);
+        let mut desc: Description<2, 2> = Description::new();
+        let ball = Shape::Sphere { center: v3(0, 0, 0), radius: fp(2) };
+        let cube = Shape::Box { center: v3(0, 0, 0), half_size: v3(2, 2, 2) };
+        let moved = Transform::identity().translate(v3(10, 0, 0));
+        desc.objects.insert(Primitive::new(ball, MaterialId(0))).ok();
+        desc.objects.insert(Primitive::new(cube, MaterialId(0)).transformed(moved)).ok();
+
+        let (u, v) = desc.leaf_uv(ObjectId(0), &v3(0, 0, 2));
+        assert!((u - half).abs() <= FP(4) && (v - fp(1)).abs() <= FP(4));
+        // Coordinates are taken in the object's own frame.
+        assert!(desc.leaf_uv(ObjectId(1), &v3(12, 1, -1)) == (fp(3) / fp(4), fp(1) / fp(4)));
+
+        let plain = Scene + Object::new(sphere_fn(v3(0, 0, 0), fp(2)), m(Material::Mirror));
+        assert!(plain.leaf_uv(ObjectId(1), &v3(1, 2, 3)) == (fp(1), fp(2)));
+        let mapped = Scene + Object::new(sphere_fn(v3(0, 0, 0), fp(2)), m(Material::Mirror))
+            .with_uv(|p: &V3| (p.z, p.x));
+        assert!(mapped.leaf_uv(ObjectId(1), &v3(1, 2, 3)) == (fp(3), fp(1)));
+    }
 }
@@ -0,0 +1,387 @@
+//! Distance functions for primitive shapes.
+//!
+//! All the functions produce closures that can be given to `Object::new`. Shapes that have an
+//! axis are aligned along z, the up direction of the scenes.
+
+use core::cmp::{max, min};
+
+use fp::{fp, FP};
+use uv::{cube, cylindrical, planar, project, spherical, toroidal};
+use v::V3;
+
+/// Length of a 2D vector.
+fn len2(x: FP, y: FP) -> FP {
+    (x * x + y * y).sqrt()
+}
+
+/// Combine separate signed distances along two axes into a distance to their intersection.
+fn extrude(dx: FP, dy: FP) -> FP {
+    min(max(dx, dy), fp(0)) + len2(max(dx, fp(0)), max(dy, fp(0)))
+}
+
+pub fn sphere_fn(center: V3, radius: FP) -> impl Fn(&V3) -> FP {
+    move |&p| (p - center).abs() - radius
+}
+
+pub fn plane_fn(normal: V3, offset: FP) -> impl Fn(&V3) -> FP {
+    move |&p| p.dot(&normal) - offset
+}
+
+/// Axis-aligned box with the given half-extents.
+pub fn box_fn(center: V3, half_size: V3) -> impl Fn(&V3) -> FP {
+    move |&p| {
+        let q = (p - center).map(|a| a.abs()) - half_size;
+        q.map(|a| max(a, fp(0))).abs() + min(max(q.x, max(q.y, q.z)), fp(0))
+    }
+}
+
+/// Axis-aligned box with edges rounded to `radius`.
+///
+/// The rounding is carved out of the box, the shape stays within `half_size`.
+pub fn rounded_box_fn(center: V3, half_size: V3, radius: FP) -> impl Fn(&V3) -> FP {
+    let inner = box_fn(center, half_size - V3::new(radius, radius, radius));
+    move |p| inner(p) - radius
+}
+
+/// Torus lying on the xy plane.
+pub fn torus_fn(center: V3, major_radius: FP, minor_radius: FP) -> impl Fn(&V3) -> FP {
+    move |&p| {
+        let p = p - center;
+        len2(len2(p.x, p.y) - major_radius, p.z) - minor_radius
+    }
+}
+
+/// Line segment from `a` to `b`.
+///
+/// The distance is unsigned, the zero set is the segment itself.
+pub fn segment_fn(a: V3, b: V3) -> impl Fn(&V3) -> FP {
+    let ab = b - a;
+    let len_sq = ab.dot(&ab);
+    move |&p| {
+        let ap = p - a;
+        let t = if len_sq > fp(0) {
+            (ap.dot(&ab) / len_sq).clamp(fp(0), fp(1))
+        } else {
+            fp(0)
+        };
+        (ap - ab * t).abs()
+    }
+}
+
+/// Line segment from `a` to `b` thickened to `radius`.
+pub fn capsule_fn(a: V3, b: V3, radius: FP) -> impl Fn(&V3) -> FP {
+    let segment = segment_fn(a, b);
+    move |p| segment(p) - radius
+}
+
+/// Capped cylinder standing along the z axis.
+pub fn cylinder_fn(center: V3, radius: FP, half_height: FP) -> impl Fn(&V3) -> FP {
+    move |&p| {
+        let p = p - center;
+        extrude(len2(p.x, p.y) - radius, p.z.abs() - half_height)
+    }
+}
+
+/// Cone standing on the xy plane with its base centered at `base` and its apex `height` above it.
+///
+/// A cone too small for the fixed-point math is the segment from the base to the apex.
+pub fn cone_fn(base: V3, radius: FP, height: FP) -> impl Fn(&V3) -> FP {
+    let half_height = height / fp(2);
+    let slant_sq = radius * radius + height * height;
+    let axis = segment_fn(base, base + V3::new(fp(0), fp(0), height));
+    move |&p| {
+        if slant_sq == fp(0) {
+            return axis(&p);
+        }
+        let p = p - base;
+        // Work on the cone's cross-section, centered at half height.
+        let qx = len2(p.x, p.y);
+        let qy = p.z - half_height;
+
+        // Vector to the base disc or the apex.
+        let cap_x = qx - min(qx, if qy < fp(0) { radius } else { fp(0) });
+        let cap_y = qy.abs() - half_height;
+
+        // Vector to the slanted side.
+        let t = ((qx * radius + (half_height - qy) * height) / slant_sq).clamp(fp(0), fp(1));
+        let side_x = qx - radius * t;
+        let side_y = qy - half_height + height * t;
+
+        let d = min(len2(cap_x, cap_y), len2(side_x, side_y));
+        if side_x < fp(0) && cap_y < fp(0) { -d } else { d }
+    }
+}
+
+/// Hexagonal prism standing along the z axis.
+///
+/// `apothem` is the distance from the axis to the middle of a side face.
+pub fn hex_prism_fn(center: V3, apothem: FP, half_height: FP) -> impl Fn(&V3) -> FP {
+    // -cos 30°, sin 30° and tan 30°.
+    const KX: FP = FP(-222);
+    const KY: FP = FP(128);
+    const KZ: FP = FP(148);
+
+    move |&p| {
+        let p = (p - center).map(|a| a.abs());
+        // Fold the first quadrant into the sector facing the y axis.
+        let fold = fp(2) * min(KX * p.x + KY * p.y, fp(0));
+        let (x, y) = (p.x - fold * KX, p.y - fold * KY);
+
+        let edge = KZ * apothem;
+        let dxy = len2(x - x.clamp(-edge, edge), y - apothem);
+        let dxy = if y < apothem { -dxy } else { dxy };
+        extrude(dxy, p.z - half_height)
+    }
+}
+
+/// Axis-aligned ellipsoid.
+///
+/// The distance is a bound, exact only on the surface and for spheres, but it never
+/// overestimates by much so it is safe to march. Ellipsoids too thin for the fixed-point math get
+/// the distance to the box around them, which is the disc, segment or point they have flattened
+/// into.
+pub fn ellipsoid_fn(center: V3, radii: V3) -> impl Fn(&V3) -> FP {
+    let largest = max(radii.x, max(radii.y, radii.z));
+    let smallest = min(radii.x, min(radii.y, radii.z));
+    // Measure in the smallest radius when it is below 1, so that its square doesn't round to zero.
+    let unit = min(smallest, fp(1));
+    let thin = unit * unit <= fp(0) || largest / fp(256) > smallest;
+    let bounds = box_fn(center, radii);
+    let radii = if thin { radii } else { radii.map(|a| a / unit) };
+    move |&p| {
+        if thin {
+            return bounds(&p);
+        }
+        let p = p - center;
+        // Far away the bounding sphere is close enough, and it keeps the ratios below in range.
+        let dist = p.abs();
+        if dist > largest * fp(2) {
+            return dist - largest;
+        }
+        let p = p.map(|a| a / unit);
+        let k0 = V3::new(p.x / radii.x, p.y / radii.y, p.z / radii.z).abs();
+        let k1 = V3::new(p.x / (radii.x * radii.x),
+                         p.y / (radii.y * radii.y),
+                         p.z / (radii.z * radii.z))
+            .abs();
+        if k1 == fp(0) {
+            // At the center, the gradient vanishes.
+            return -smallest;
+        }
+        k0 * (k0 - fp(1)) / k1 * unit
+    }
+}
+
+/// Primitive shape as plain data, for scenes built at runtime.
+#[derive(Copy, Clone)]
+pub enum Shape {
+    Sphere { center: V3, radius: FP },
+    Plane { normal: V3, offset: FP },
+    Box { center: V3, half_size: V3 },
+    RoundedBox { center: V3, half_size: V3, radius: FP },
+    Torus { center: V3, major_radius: FP, minor_radius: FP },
+    Segment { a: V3, b: V3 },
+    Capsule { a: V3, b: V3, radius: FP },
+    Cylinder { center: V3, radius: FP, half_height: FP },
+    Cone { base: V3, radius: FP, height: FP },
+    HexPrism { center: V3, apothem: FP, half_height: FP },
+    Ellipsoid { center: V3, radii: V3 },
+}
+
+impl Shape {
+    pub fn distance(&self, pos: &V3) -> FP {
+        match *self {
+            Shape::Sphere { center, radius } => sphere_fn(center, radius)(pos),
+            Shape::Plane { normal, offset } => plane_fn(normal, offset)(pos),
+            Shape::Box { center, half_size } => box_fn(center, half_size)(pos),
+            Shape::RoundedBox { center, half_size, radius } => {
+                rounded_box_fn(center, half_size, radius)(pos)
+            }
+            Shape::Torus { center, major_radius, minor_radius } => {
+                torus_fn(center, major_radius, minor_radius)(pos)
+            }
+            Shape::Segment { a, b } => segment_fn(a, b)(pos),
+            Shape::Capsule { a, b, radius } => capsule_fn(a, b, radius)(pos),
+            Shape::Cylinder { center, radius, half_height } => {
+                cylinder_fn(center, radius, half_height)(pos)
+            }
+            Shape::Cone { base, radius, height } => cone_fn(base, radius, height)(pos),
+            Shape::HexPrism { center, apothem, half_height } => {
+                hex_prism_fn(center, apothem, half_height)(pos)
+            }
+            Shape::Ellipsoid { center, radii } => ellipsoid_fn(center, radii)(pos),
+        }
+    }
+
+    /// Surface coordinates at a point on the shape, see `uv` for the mappings.
+    ///
+    /// Spheres and ellipsoids are mapped spherically, boxes get the image on every face and the
+    /// shapes standing along z are wrapped cylindrically. Planes and the shapes without a
+    /// natural mapping are projected in scene units.
+    pub fn uv(&self, pos: &V3) -> (FP, FP) {
+        match *self {
+            Shape::Sphere { center, .. } |
+            Shape::Ellipsoid { center, .. } => spherical(&(*pos - center)),
+            Shape::Plane { normal, .. } => project(pos, &normal),
+            Shape::Box { center, half_size } |
+            Shape::RoundedBox { center, half_size, .. } => cube(&(*pos - center), &half_size),
+            Shape::Cylinder { center, half_height, .. } |
+            Shape::HexPrism { center, half_height, .. } => {
+                cylindrical(&(*pos - center), half_height)
+            }
+            Shape::Cone { base, height, .. } => {
+                let center = base + V3::new(fp(0), fp(0), height / fp(2));
+                cylindrical(&(*pos - center), height / fp(2))
+            }
+            Shape::Torus { center, major_radius, .. } => toroidal(&(*pos - center), major_radius),
+            Shape::Segment { .. } |
+            Shape::Capsule { .. } => planar(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::{fp, FP};
+    use v::{v3, V3};
+    use super::*;
+
+    fn on_surface(d: FP) -> bool {
+        d.abs() <= FP(8)
+    }
+
+    #[test]
+    fn test_box() {
+        let f = box_fn(v3(1, 1, 1), v3(2, 3, 4));
+        assert!(f(&v3(1, 1, 1)) == fp(-2));
+        assert!(on_surface(f(&v3(3, 1, 1))));
+        assert!(on_surface(f(&v3(3, 4, 5))));
+        assert!(f(&v3(1, 1, 7)) == fp(2));
+        assert!(f(&v3(6, 8, 1)) == fp(5));
+    }
+
+    #[test]
+    fn test_rounded_box() {
+        let f = rounded_box_fn(v3(0, 0, 0), v3(4, 4, 4), fp(1));
+        assert!(f(&v3(0, 0, 0)) < fp(0));
+        assert!(on_surface(f(&v3(4, 0, 0))));
+        // Corners are rounded off.
+        assert!(f(&v3(4, 4, 4)) > fp(0));
+        assert!(f(&v3(0, 0, 6)) == fp(2));
+    }
+
+    #[test]
+    fn test_torus() {
+        let f = torus_fn(v3(0, 0, 1), fp(4), fp(1));
+        assert!(f(&v3(4, 0, 1)) == fp(-1));
+        assert!(on_surface(f(&v3(0, 5, 1))));
+        assert!(on_surface(f(&v3(0, -4, 2))));
+        // The hole in the middle.
+        assert!(f(&v3(0, 0, 1)) == fp(3));
+    }
+
+    #[test]
+    fn test_segment_and_capsule() {
+        let f = segment_fn(v3(0, 0, 0), v3(0, 0, 4));
+        assert!(f(&v3(0, 0, 2)) == fp(0));
+        assert!(f(&v3(3, 0, 2)) == fp(3));
+        assert!(f(&v3(0, 0, 7)) == fp(3));
+
+        let f = capsule_fn(v3(0, 0, 0), v3(0, 0, 4), fp(1));
+        assert!(f(&v3(0, 0, 2)) == fp(-1));
+        assert!(on_surface(f(&v3(1, 0, 2))));
+        assert!(on_surface(f(&v3(0, 0, -1))));
+        assert!(f(&v3(0, 4, 4)) == fp(3));
+
+        // Degenerate segment is a point.
+        let f = segment_fn(v3(1, 1, 1), v3(1, 1, 1));
+        assert!(f(&v3(1, 1, 3)) == fp(2));
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let f = cylinder_fn(v3(0, 0, 0), fp(2), fp(3));
+        assert!(f(&v3(0, 0, 0)) == fp(-2));
+        assert!(on_surface(f(&v3(2, 0, 1))));
+        assert!(on_surface(f(&v3(1, 0, 3))));
+        assert!(f(&v3(0, 0, 5)) == fp(2));
+        assert!(on_surface(f(&v3(5, 0, 7)) - fp(5)));
+    }
+
+    #[test]
+    fn test_cone() {
+        let f = cone_fn(v3(0, 0, 0), fp(2), fp(4));
+        assert!(f(&v3(0, 0, 1)) < fp(0));
+        assert!(on_surface(f(&v3(0, 0, 4))));
+        assert!(on_surface(f(&v3(1, 0, 0))));
+        assert!(on_surface(f(&v3(1, 0, 2))));
+        assert!(f(&v3(0, 0, -2)) == fp(2));
+        assert!(f(&v3(2, 0, 3)) > fp(0));
+        assert!(f(&v3(0, 0, 6)) == fp(2));
+
+        // Too small for the slant, so it is the axis.
+        let f = cone_fn(v3(0, 0, 0), FP(12), FP(12));
+        assert!(f(&v3(0, 0, 0)) == fp(0));
+        assert!(f(&v3(0, 3, 0)) == fp(3));
+        let f = cone_fn(v3(0, 0, 0), fp(0), fp(0));
+        assert!(f(&v3(0, 0, 2)) == fp(2));
+    }
+
+    #[test]
+    fn test_hex_prism() {
+        let f = hex_prism_fn(v3(0, 0, 0), fp(2), fp(1));
+        assert!(f(&v3(0, 0, 0)) < fp(0));
+        assert!(on_surface(f(&v3(0, 2, 0))));
+        assert!(on_surface(f(&v3(0, -2, 0))));
+        assert!(on_surface(f(&v3(0, 0, 1))));
+        assert!(f(&v3(0, 5, 0)) == fp(3));
+        // Corner on the x axis sits at apothem / cos 30°.
+        assert!(on_surface(f(&V3::new(FP(591), fp(0), fp(0)))));
+    }
+
+    #[test]
+    fn test_ellipsoid() {
+        let f = ellipsoid_fn(v3(0, 0, 0), v3(4, 2, 1));
+        assert!(f(&v3(0, 0, 0)) < fp(0));
+        assert!(f(&v3(1, 0, 0)) < fp(0));
+        assert!(on_surface(f(&v3(4, 0, 0))));
+        assert!(on_surface(f(&v3(0, 2, 0))));
+        assert!(on_surface(f(&v3(0, 0, -1))));
+        assert!(f(&v3(0, 0, 3)) > fp(0));
+        assert!(f(&v3(6, 0, 0)) > fp(0));
+        assert!(f(&v3(500, 0, 0)) == fp(496));
+
+        // Radii below 1 are measured in themselves.
+        let f = ellipsoid_fn(v3(0, 0, 0), V3::new(fp(1) / fp(8), fp(1), fp(1)));
+        assert!(f(&v3(0, 0, 0)) < fp(0));
+        assert!(on_surface(f(&V3::new(fp(1) / fp(8), fp(0), fp(0)))));
+        assert!(on_surface(f(&v3(0, 1, 0))));
+        assert!(f(&v3(2, 0, 0)) > fp(1));
+        // Too thin to square the radius, so it is a disc.
+        let f = ellipsoid_fn(v3(0, 0, 0), V3::new(FP(12), fp(1), fp(1)));
+        assert!(f(&v3(0, 0, 0)) < fp(0));
+        assert!(on_surface(f(&v3(3, 0, 0)) - (fp(3) - FP(12))));
+        assert!(f(&v3(500, 0, 0)) > fp(0));
+    }
+
+    #[test]
+    fn test_flat_uv() {
+        let half = fp(1) / fp(2);
+        let panel = Shape::Box { center: v3(10, 0, 0), half_size: v3(2, 2, 0) };
+        assert!(panel.uv(&v3(11, 1, 0)) == (fp(3) / fp(4), fp(3) / fp(4)));
+        let disk = Shape::Cylinder { center: v3(0, 0, 0), radius: fp(2), half_height: fp(0) };
+        assert!(disk.uv(&v3(0, 1, 0)).1 == half);
+        let flat_cone = Shape::Cone { base: v3(0, 0, 0), radius: fp(2), height: fp(0) };
+        assert!(flat_cone.uv(&v3(0, 1, 0)).1 == half);
+    }
+
+    #[test]
+    fn test_sphere_and_plane() {
+        let f = sphere_fn(v3(1, 2, 3), fp(2));
+        assert!(f(&v3(1, 2, 3)) == fp(-2));
+        assert!(f(&v3(1, 2, 5)) == fp(0));
+        let f = plane_fn(v3(0, 0, 1), fp(1));
+        assert!(f(&v3(5, 5, 0)) == fp(-1));
+        assert!(f(&v3(5, 5, 1)) == fp(0));
+    }
+}
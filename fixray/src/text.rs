@@ -0,0 +1,639 @@
+//! Text scene description format.
+//!
+//! A scene is a list of statements, one per line. Values are separated by whitespace and `#`
+//! starts a comment that runs to the end of the line. Numbers are decimals like `-1.25`, angles
+//! are in degrees.
+//!
+//! ```text
+//! camera 0 0 4  8 4 -1  0 0 1             # position, view direction, up
+//! light 1 1 -4                            # direction the light travels in, [intensity]
+//! point_light 0 0 10  0.5 20               # position, intensity, range
+//! spot_light 0 0 10  0 0 -1  30  1 20      # position, direction, angle, intensity, range
+//!
+//! material red surface yellow red black   # highlight, color, shadow
+//! material chrome mirror
+//! material gold tinted yellow             # mirror color
+//! material lens glass 1.5                 # index of refraction
+//! material green surface green green black
+//! material white surface white white black
+//! material floor checkerboard green white
+//! material glow finish blue 0 0.5 0.25     # albedo, reflectivity, specular, emission
+//!
+//! sphere red 10 5 2  3                    # center, radius
+//! translate 0 15 2
+//! rotate z 45
+//! box chrome 0 0 0  2 2 2                 # center, half-size
+//! reset
+//! plane floor 0 0 1  0                    # normal, offset
+//! ```
+//!
+//! Every light statement adds a light to the scene. A scene without light statements gets a
+//! single light shining straight down, like `light 0 0 -1`, from
+//! `Description::add_default_light`. Point and spot lights fade to half their intensity at their
+//! range, or not at all if the range is zero.
+//!
+//! Materials are named and must be defined before the objects that use them. Redefining a
+//! material changes the objects that were already added with it. `translate`,
+//! `rotate` and `scale` accumulate a transform that applies to all the following objects until
+//! `reset`. Scale factors must be positive, and the accumulated scale must stay between
+//! `MIN_SCALE` and `MAX_SCALE`. The camera up vector can't point along the view direction.
+//!
+//! The object statements and their parameters after the material name are
+//!
+//! ```text
+//! sphere       center radius
+//! plane        normal offset
+//! box          center half-size
+//! rounded_box  center half-size radius
+//! torus        center major-radius minor-radius
+//! segment      a b
+//! capsule      a b radius
+//! cylinder     center radius half-height
+//! cone         base radius height   # not both tiny
+//! hex_prism    center apothem half-height
+//! ellipsoid    center radii         # all radii positive and not tiny
+//! ```
+
+use fp::{fp, FP, DECIMAL_BITS, PI};
+use v::V3;
+use geom::{Frustum, Transform};
+use light::Light;
+use scene::{Description, Primitive};
+use material::{Finish, MAX_NAME};
+use rgb::Rgb;
+use shape::Shape;
+use {Color, Material, Paint};
+
+pub use material::MAX_MATERIALS;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorKind {
+    UnknownStatement,
+    MissingValue,
+    ExtraValue,
+    BadNumber,
+    BadColor,
+    BadAxis,
+    /// A direction vector was zero.
+    ZeroVector,
+    /// A scale or size that must be positive was zero or negative.
+    NotPositive,
+    /// A scale or size too small or too large for the fixed-point math.
+    OutOfRange,
+    /// Camera up vector too close to the view direction.
+    ParallelVectors,
+    UnknownMaterial,
+    /// Checkerboard made of materials that aren't uniform.
+    NestedPattern,
+    /// Material name longer than `MAX_NAME`.
+    NameTooLong,
+    TooManyMaterials,
+    TooManyObjects,
+    TooManyLights,
+}
+
+/// Parse error with 1-based line and column.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Error {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+}
+
+/// Build a scene from its text description.
+pub fn parse<const N: usize>(input: &[u8]) -> Result<Description<N>, Error> {
+    let mut desc: Description<N> = Description::new();
+    let mut transform = Transform::identity();
+
+    for (i, text) in input.split(|&c| c == b'\n').enumerate() {
+        let mut line = Line {
+            text,
+            pos: 0,
+            line: i + 1,
+        };
+        let (column, keyword) = match line.token() {
+            Some(t) => t,
+            None => continue,
+        };
+
+        match keyword {
+            b"camera" => {
+                let (origin, dir) = (line.vector()?, line.direction()?);
+                let column = line.skip_space();
+                desc.camera = match Frustum::new(origin, dir, line.direction()?) {
+                    Some(camera) => camera,
+                    None => return Err(line.error(column, ErrorKind::ParallelVectors)),
+                };
+            }
+            b"light" => {
+                let light = Light::Directional {
+                    dir: line.direction()?,
+                    intensity: line.optional_number(fp(1))?,
+                };
+                add_light(&mut desc, light, &line, column)?;
+            }
+            b"point_light" => {
+                let light = Light::Point {
+                    pos: line.vector()?,
+                    intensity: line.number()?,
+                    range: line.number()?,
+                };
+                add_light(&mut desc, light, &line, column)?;
+            }
+            b"spot_light" => {
+                let light = Light::Spot {
+                    pos: line.vector()?,
+                    dir: line.direction()?,
+                    angle: line.number()? * PI / fp(180),
+                    intensity: line.number()?,
+                    range: line.number()?,
+                };
+                add_light(&mut desc, light, &line, column)?;
+            }
+            b"material" => {
+                let (column, name) = line.word()?;
+                if name.len() > MAX_NAME {
+                    return Err(line.error(column, ErrorKind::NameTooLong));
+                }
+                let paint = parse_paint(&desc, &mut line)?;
+                if desc.materials.insert(name, paint).is_none() {
+                    return Err(line.error(column, ErrorKind::TooManyMaterials));
+                }
+            }
+            b"translate" => transform = transform.translate(line.vector()?),
+            b"rotate" => {
+                let (column, axis) = line.word()?;
+                let angle = line.number()? * PI / fp(180);
+                transform = match axis {
+                    b"x" => transform.rotate_x(angle),
+                    b"y" => transform.rotate_y(angle),
+                    b"z" => transform.rotate_z(angle),
+                    _ => return Err(line.error(column, ErrorKind::BadAxis)),
+                };
+            }
+            b"scale" => {
+                let column = line.skip_space();
+                transform = match transform.checked_scale(line.positive()?) {
+                    Some(t) => t,
+                    None => return Err(line.error(column, ErrorKind::OutOfRange)),
+                };
+            }
+            b"reset" => transform = Transform::identity(),
+            _ => {
+                let parse_shape = match shape_parser(keyword) {
+                    Some(f) => f,
+                    None => return Err(line.error(column, ErrorKind::UnknownStatement)),
+                };
+                let (name_column, name) = line.word()?;
+                let material = match desc.materials.find(name) {
+                    Some(id) => id,
+                    None => return Err(line.error(name_column, ErrorKind::UnknownMaterial)),
+                };
+                let object = Primitive::new(parse_shape(&mut line)?, material)
+                    .transformed(transform);
+                if desc.objects.insert(object).is_err() {
+                    return Err(line.error(column, ErrorKind::TooManyObjects));
+                }
+            }
+        }
+
+        line.end()?;
+    }
+
+    desc.add_default_light();
+    Ok(desc)
+}
+
+fn add_light<const N: usize>(desc: &mut Description<N>,
+                             light: Light,
+                             line: &Line,
+                             column: usize)
+                             -> Result<(), Error> {
+    match desc.lights.push(light) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(line.error(column, ErrorKind::TooManyLights)),
+    }
+}
+
+/// Parser for the parameters of a shape statement.
+type ShapeParser = fn(&mut Line) -> Result<Shape, Error>;
+
+/// Look up the parser for an object keyword.
+fn shape_parser(keyword: &[u8]) -> Option<ShapeParser> {
+    Some(match keyword {
+        b"sphere" => |line| {
+            Ok(Shape::Sphere {
+                center: line.vector()?,
+                radius: line.number()?,
+            })
+        },
+        b"plane" => |line| {
+            Ok(Shape::Plane {
+                normal: line.direction()?,
+                offset: line.number()?,
+            })
+        },
+        b"box" => |line| {
+            Ok(Shape::Box {
+                center: line.vector()?,
+                half_size: line.vector()?,
+            })
+        },
+        b"rounded_box" => |line| {
+            Ok(Shape::RoundedBox {
+                center: line.vector()?,
+                half_size: line.vector()?,
+                radius: line.number()?,
+            })
+        },
+        b"torus" => |line| {
+            Ok(Shape::Torus {
+                center: line.vector()?,
+                major_radius: line.number()?,
+                minor_radius: line.number()?,
+            })
+        },
+        b"segment" => |line| {
+            Ok(Shape::Segment {
+                a: line.vector()?,
+                b: line.vector()?,
+            })
+        },
+        b"capsule" => |line| {
+            Ok(Shape::Capsule {
+                a: line.vector()?,
+                b: line.vector()?,
+                radius: line.number()?,
+            })
+        },
+        b"cylinder" => |line| {
+            Ok(Shape::Cylinder {
+                center: line.vector()?,
+                radius: line.number()?,
+                half_height: line.number()?,
+            })
+        },
+        b"cone" => |line| {
+            let base = line.vector()?;
+            let column = line.skip_space();
+            let (radius, height) = (line.number()?, line.number()?);
+            if radius * radius + height * height == fp(0) {
+                return Err(line.error(column, ErrorKind::OutOfRange));
+            }
+            Ok(Shape::Cone { base, radius, height })
+        },
+        b"hex_prism" => |line| {
+            Ok(Shape::HexPrism {
+                center: line.vector()?,
+                apothem: line.number()?,
+                half_height: line.number()?,
+            })
+        },
+        b"ellipsoid" => |line| {
+            Ok(Shape::Ellipsoid {
+                center: line.vector()?,
+                radii: V3::new(line.length()?, line.length()?, line.length()?),
+            })
+        },
+        _ => return None,
+    })
+}
+
+fn parse_paint<const N: usize>(desc: &Description<N>, line: &mut Line) -> Result<Paint, Error> {
+    let (column, kind) = line.word()?;
+    match kind {
+        b"mirror" => Ok(Paint::Solid(Material::Mirror)),
+        b"tinted" => Ok(Paint::Solid(Material::Tinted(line.color()?))),
+        b"glass" => Ok(Paint::Solid(Material::Glass(line.positive()?))),
+        b"surface" => {
+            Ok(Paint::Solid(Material::Surface(line.color()?, line.color()?, line.color()?)))
+        }
+        b"finish" => {
+            Ok(Paint::Solid(Material::Finish(Finish {
+                albedo: Rgb::from(line.color()?),
+                reflectivity: line.number()?,
+                specular: line.number()?,
+                emission: line.number()?,
+            })))
+        }
+        b"checkerboard" => Ok(Paint::Checkerboard(solid(desc, line)?, solid(desc, line)?)),
+        _ => Err(line.error(column, ErrorKind::UnknownStatement)),
+    }
+}
+
+/// Reference to an earlier uniform material.
+fn solid<const N: usize>(desc: &Description<N>, line: &mut Line) -> Result<Material, Error> {
+    let (column, name) = line.word()?;
+    match desc.materials.find(name).and_then(|id| desc.materials.get(id)) {
+        Some(&Paint::Solid(m)) => Ok(m),
+        Some(_) => Err(line.error(column, ErrorKind::NestedPattern)),
+        None => Err(line.error(column, ErrorKind::UnknownMaterial)),
+    }
+}
+
+fn is_space(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\r'
+}
+
+/// Tokenizer for a single line.
+struct Line<'a> {
+    text: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, column: usize, kind: ErrorKind) -> Error {
+        Error {
+            line: self.line,
+            column,
+            kind,
+        }
+    }
+
+    /// Move to the start of the next token and return its column.
+    fn skip_space(&mut self) -> usize {
+        while self.pos < self.text.len() && is_space(self.text[self.pos]) {
+            self.pos += 1;
+        }
+        self.pos + 1
+    }
+
+    /// Next whitespace-separated token and its column.
+    fn token(&mut self) -> Option<(usize, &'a [u8])> {
+        self.skip_space();
+        if self.pos == self.text.len() || self.text[self.pos] == b'#' {
+            return None;
+        }
+
+        let start = self.pos;
+        while self.pos < self.text.len() && !is_space(self.text[self.pos]) &&
+              self.text[self.pos] != b'#' {
+            self.pos += 1;
+        }
+        Some((start + 1, &self.text[start..self.pos]))
+    }
+
+    fn word(&mut self) -> Result<(usize, &'a [u8]), Error> {
+        match self.token() {
+            Some(t) => Ok(t),
+            None => Err(self.error(self.pos + 1, ErrorKind::MissingValue)),
+        }
+    }
+
+    fn number(&mut self) -> Result<FP, Error> {
+        let (column, word) = self.word()?;
+        match parse_number(word) {
+            Some(x) => Ok(x),
+            None => Err(self.error(column, ErrorKind::BadNumber)),
+        }
+    }
+
+    /// Number that must be greater than zero.
+    fn positive(&mut self) -> Result<FP, Error> {
+        let column = self.skip_space();
+        let x = self.number()?;
+        if x <= fp(0) {
+            return Err(self.error(column, ErrorKind::NotPositive));
+        }
+        Ok(x)
+    }
+
+    /// Positive size that doesn't vanish when squared.
+    fn length(&mut self) -> Result<FP, Error> {
+        let column = self.skip_space();
+        let x = self.positive()?;
+        if x * x == fp(0) {
+            return Err(self.error(column, ErrorKind::OutOfRange));
+        }
+        Ok(x)
+    }
+
+    /// Number that can be left out at the end of the line.
+    fn optional_number(&mut self, default: FP) -> Result<FP, Error> {
+        match self.token() {
+            Some((column, word)) => {
+                match parse_number(word) {
+                    Some(x) => Ok(x),
+                    None => Err(self.error(column, ErrorKind::BadNumber)),
+                }
+            }
+            None => Ok(default),
+        }
+    }
+
+    fn vector(&mut self) -> Result<V3, Error> {
+        Ok(V3::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    /// Vector normalized to unit length.
+    fn direction(&mut self) -> Result<V3, Error> {
+        let column = self.skip_space();
+        let v = self.vector()?;
+        if v.dot(&v) == fp(0) {
+            return Err(self.error(column, ErrorKind::ZeroVector));
+        }
+        Ok(v.normalized())
+    }
+
+    fn color(&mut self) -> Result<Color, Error> {
+        let (column, word) = self.word()?;
+        Ok(match word {
+            b"black" => Color::Black,
+            b"red" => Color::Red,
+            b"green" => Color::Green,
+            b"yellow" => Color::Yellow,
+            b"blue" => Color::Blue,
+            b"magenta" => Color::Magenta,
+            b"cyan" => Color::Cyan,
+            b"white" => Color::White,
+            _ => return Err(self.error(column, ErrorKind::BadColor)),
+        })
+    }
+
+    /// Check that the line has nothing left.
+    fn end(&mut self) -> Result<(), Error> {
+        match self.token() {
+            Some((column, _)) => Err(self.error(column, ErrorKind::ExtraValue)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a decimal number, rounding it to the nearest fixed-point value.
+fn parse_number(s: &[u8]) -> Option<FP> {
+    let (negative, s) = match s.first() {
+        Some(&b'-') => (true, &s[1..]),
+        _ => (false, s),
+    };
+    let mut parts = s.splitn(2, |&c| c == b'.');
+    let int = parts.next().unwrap_or(b"");
+    let frac = parts.next().unwrap_or(b"");
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+
+    let mut raw: i64 = 0;
+    for &c in int {
+        if !c.is_ascii_digit() || raw > i32::MAX as i64 {
+            return None;
+        }
+        raw = raw * 10 + (c - b'0') as i64;
+    }
+    raw <<= DECIMAL_BITS;
+
+    // Digits past the fixed-point resolution make no difference.
+    let (mut num, mut den) = (0i64, 1i64);
+    for &c in frac {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        if den < 1_000_000 {
+            num = num * 10 + (c - b'0') as i64;
+            den *= 10;
+        }
+    }
+    raw += ((num << DECIMAL_BITS) + den / 2) / den;
+
+    if raw > i32::MAX as i64 {
+        return None;
+    }
+    Some(FP(if negative { -raw } else { raw } as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::{fp, FP};
+    use v::v3;
+    use scene::{Body, SurfaceContext};
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        assert!(parse_number(b"12") == Some(fp(12)));
+        assert!(parse_number(b"-1.5") == Some(FP(-384)));
+        assert!(parse_number(b".25") == Some(FP(64)));
+        assert!(parse_number(b"3.") == Some(fp(3)));
+        assert!(parse_number(b"0.333333333") == Some(FP(85)));
+        assert!(parse_number(b"-").is_none());
+        assert!(parse_number(b"1.2.3").is_none());
+        assert!(parse_number(b"1e3").is_none());
+        assert!(parse_number(b"99999999").is_none());
+    }
+
+    #[test]
+    fn test_parse() {
+        let scene = b"
+            # Test scene
+            camera 0 0 4  8 4 -1  0 0 1
+            light 0 0 -2
+            point_light 0 0 10  0.5 20
+
+            material red surface yellow red black
+            material chrome mirror   # Shiny
+            material floor checkerboard red red
+            material glow finish blue 0 0.5 0.25
+            material gold tinted yellow
+            material lens glass 1.5
+
+            sphere red 10 5 2  3
+            translate 0 10 0
+            box chrome 0 0 0  1 1 1
+            reset
+            plane floor 0 0 2  0
+        ";
+        let desc = parse::<4>(scene).ok().unwrap();
+        assert!(desc.objects.len() == 3);
+        assert!(desc.lights.len() == 2);
+        match desc.lights[0] {
+            Light::Directional { dir, intensity } => {
+                assert!(dir.z == fp(-1));
+                assert!(intensity == fp(1));
+            }
+            _ => panic!("Wrong light"),
+        }
+        match desc.lights[1] {
+            Light::Point { range, .. } => assert!(range == fp(20)),
+            _ => panic!("Wrong light"),
+        }
+        assert!(desc.camera.origin.z == fp(4));
+        assert!(desc.objects.distance(&v3(10, 5, 2)) == fp(-3));
+        assert!(desc.objects.distance(&v3(0, 10, 3)) == fp(2));
+        assert!(desc.objects.distance(&v3(-20, -20, 1)) == fp(1));
+        assert!(desc.materials.len() == 6);
+        match desc.material(&SurfaceContext::at(v3(10, 5, 5))) {
+            Material::Surface(Color::Yellow, Color::Red, Color::Black) => {}
+            _ => panic!("Wrong material"),
+        }
+        match desc.materials.get(desc.materials.find(b"glow").unwrap()) {
+            Some(&Paint::Solid(Material::Finish(f))) => {
+                assert!(f.albedo == Rgb::from(Color::Blue));
+                assert!(f.specular == fp(1) / fp(2));
+                assert!(f.emission == fp(1) / fp(4));
+            }
+            _ => panic!("Wrong material"),
+        }
+        match desc.materials.get(desc.materials.find(b"gold").unwrap()) {
+            Some(&Paint::Solid(Material::Tinted(Color::Yellow))) => {}
+            _ => panic!("Wrong material"),
+        }
+        match desc.materials.get(desc.materials.find(b"lens").unwrap()) {
+            Some(&Paint::Solid(Material::Glass(ior))) => assert!(ior == fp(3) / fp(2)),
+            _ => panic!("Wrong material"),
+        }
+    }
+
+    #[test]
+    fn test_default_light() {
+        let desc = parse::<1>(b"camera 0 0 0  1 0 0  0 0 1").ok().unwrap();
+        assert!(desc.lights.len() == 1);
+        match desc.lights[0] {
+            Light::Directional { dir, intensity } => {
+                assert!(dir == v3(0, 0, -1) && intensity == fp(1));
+            }
+            _ => panic!("Wrong light"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        fn err(input: &[u8]) -> (usize, usize, ErrorKind) {
+            let e = parse::<2>(input).err().unwrap();
+            (e.line, e.column, e.kind)
+        }
+
+        assert!(err(b"\n  frob 1 2 3") == (2, 3, ErrorKind::UnknownStatement));
+        assert!(err(b"sphere red 1 2 3 4") == (1, 8, ErrorKind::UnknownMaterial));
+        assert!(err(b"light 1 x 3") == (1, 9, ErrorKind::BadNumber));
+        assert!(err(b"light 1 2") == (1, 10, ErrorKind::MissingValue));
+        assert!(err(b"light 1 2 3 4 5") == (1, 15, ErrorKind::ExtraValue));
+        assert!(err(b"light 1 2 3 x") == (1, 13, ErrorKind::BadNumber));
+        assert!(err(b"light 0 0 0") == (1, 7, ErrorKind::ZeroVector));
+        assert!(err(b"rotate w 10") == (1, 8, ErrorKind::BadAxis));
+        assert!(err(b"scale 0") == (1, 7, ErrorKind::NotPositive));
+        assert!(err(b"scale -2") == (1, 7, ErrorKind::NotPositive));
+        assert!(err(b"material m mirror\nellipsoid m 0 0 0  4 0 1") ==
+                (2, 22, ErrorKind::NotPositive));
+        assert!(err(b"material g glass 0") == (1, 18, ErrorKind::NotPositive));
+
+        // Values that pass on their own but break the fixed-point math.
+        assert!(err(b"camera 0 0 0  1 0 0  2 0 0") == (1, 22, ErrorKind::ParallelVectors));
+        assert!(err(b"camera 0 0 0  0 0 1  0 0.1 -1") == (1, 22, ErrorKind::ParallelVectors));
+        assert!(err(b"scale 0.01") == (1, 7, ErrorKind::OutOfRange));
+        assert!(err(b"scale 0.1\nscale 0.1\nscale 0.1") == (2, 7, ErrorKind::OutOfRange));
+        assert!(err(b"scale 4\nscale 8") == (2, 7, ErrorKind::OutOfRange));
+        assert!(err(b"material m mirror\nellipsoid m 0 0 0  0.05 1 1") ==
+                (2, 20, ErrorKind::OutOfRange));
+        assert!(err(b"material m mirror\ncone m 0 0 0  0 0") == (2, 15, ErrorKind::OutOfRange));
+        assert!(err(b"material m mirror\ncone m 0 0 0  0.05 0.05") ==
+                (2, 15, ErrorKind::OutOfRange));
+        assert!(err(b"material m surface red red pink") == (1, 28, ErrorKind::BadColor));
+        assert!(err(b"material m mirror\nmaterial c checkerboard m q") ==
+                (2, 27, ErrorKind::UnknownMaterial));
+        assert!(err(b"material much_too_long_name mirror") == (1, 10, ErrorKind::NameTooLong));
+        assert!(err(b"material m mirror\nsphere m 0 0 0 1\nsphere m 0 0 0 1\nsphere m 0 0 0 1") ==
+                (4, 1, ErrorKind::TooManyObjects));
+        assert!(err(b"light 0 0 -1\nlight 0 0 -1\nlight 0 0 -1\nlight 0 0 -1\nlight 0 0 -1") ==
+                (5, 1, ErrorKind::TooManyLights));
+    }
+}
@@ -0,0 +1,254 @@
+//! Procedural textures.
+//!
+//! Patterns are functions from position to a value between 0 and 1. `select` turns a pattern
+//! into a material function for `Object::new`, and `gradient` and `mix` turn patterns into
+//! colors. The patterns with a period repeat once per unit of pattern space, use a `Mapping` to
+//! size and place them.
+
+use fp::{fp, FP, PI};
+use noise::fbm;
+use rgb::Rgb;
+use v::V3;
+
+/// Placement of a pattern in the scene.
+#[derive(Copy, Clone)]
+pub struct Mapping {
+    /// Size of one pattern unit in scene units.
+    pub scale: FP,
+    /// Scene position of the pattern origin.
+    pub offset: V3,
+}
+
+impl Mapping {
+    pub fn new(scale: FP, offset: V3) -> Mapping {
+        Mapping {
+            scale,
+            offset,
+        }
+    }
+
+    pub fn scale(scale: FP) -> Mapping {
+        Mapping::new(scale, V3::new(fp(0), fp(0), fp(0)))
+    }
+
+    /// Position in pattern space.
+    pub fn apply(&self, pos: &V3) -> V3 {
+        (*pos - self.offset).map(|c| c / self.scale)
+    }
+}
+
+impl Default for Mapping {
+    fn default() -> Mapping {
+        Mapping::scale(fp(1))
+    }
+}
+
+fn fract(x: FP) -> FP {
+    x - fp(x.to_i32())
+}
+
+fn parity(n: i32) -> FP {
+    fp(n & 1)
+}
+
+/// Squares alternating on the xy plane.
+pub fn checker2(mapping: Mapping) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let p = mapping.apply(pos);
+        parity(p.x.to_i32() + p.y.to_i32())
+    }
+}
+
+/// Cubes alternating in space.
+pub fn checker3(mapping: Mapping) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let p = mapping.apply(pos);
+        parity(p.x.to_i32() + p.y.to_i32() + p.z.to_i32())
+    }
+}
+
+/// Stripes across the x axis.
+pub fn stripes(mapping: Mapping) -> impl Fn(&V3) -> FP {
+    move |pos| parity(mapping.apply(pos).x.to_i32())
+}
+
+/// Rings around the z axis.
+pub fn rings(mapping: Mapping) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let p = mapping.apply(pos);
+        parity((p.x * p.x + p.y * p.y).sqrt().to_i32())
+    }
+}
+
+/// Lines of `width` along the x and y axes, 1 on the lines.
+pub fn grid(mapping: Mapping, width: FP) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let p = mapping.apply(pos);
+        if fract(p.x) < width || fract(p.y) < width {
+            fp(1)
+        } else {
+            fp(0)
+        }
+    }
+}
+
+/// Balls of `radius` in the middle of every cell, 1 inside the dots.
+pub fn dots(mapping: Mapping, radius: FP) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let d = mapping.apply(pos).map(|c| fract(c) - fp(1) / fp(2));
+        if d.dot(&d) < radius * radius {
+            fp(1)
+        } else {
+            fp(0)
+        }
+    }
+}
+
+/// Veins across the x axis, bent by noise as much as `turbulence`.
+pub fn marble(mapping: Mapping, seed: u32, turbulence: FP) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let p = mapping.apply(pos);
+        let x = p.x + fbm(seed, &p, 3) * turbulence * fp(4);
+        (((x * PI).sin() + fp(1)) / fp(2)).clamp(fp(0), fp(1))
+    }
+}
+
+/// Growth rings around the z axis, warped by noise as much as `turbulence`.
+pub fn wood(mapping: Mapping, seed: u32, turbulence: FP) -> impl Fn(&V3) -> FP {
+    move |pos| {
+        let p = mapping.apply(pos);
+        let r = (p.x * p.x + p.y * p.y).sqrt() + fbm(seed, &p, 2) * turbulence;
+        fract(r)
+    }
+}
+
+/// Value `a` where the pattern is under one half and `b` elsewhere.
+///
+/// Works for materials and colors alike.
+pub fn select<T, P>(pattern: P, a: T, b: T) -> impl Fn(&V3) -> T
+    where T: Copy,
+          P: Fn(&V3) -> FP
+{
+    move |pos| if pattern(pos) < fp(1) / fp(2) { a } else { b }
+}
+
+/// Color going from `a` to `b` as the pattern goes from 0 to 1.
+pub fn gradient<P>(pattern: P, a: Rgb, b: Rgb) -> impl Fn(&V3) -> Rgb
+    where P: Fn(&V3) -> FP
+{
+    move |pos| a.mix(b, pattern(pos))
+}
+
+/// Blend between two color textures by a pattern.
+pub fn mix<F, G, P>(a: F, b: G, pattern: P) -> impl Fn(&V3) -> Rgb
+    where F: Fn(&V3) -> Rgb,
+          G: Fn(&V3) -> Rgb,
+          P: Fn(&V3) -> FP
+{
+    move |pos| a(pos).mix(b(pos), pattern(pos))
+}
+
+/// Texture `below` under `height` and `above` from there up.
+pub fn by_height<T, F, G>(height: FP, below: F, above: G) -> impl Fn(&V3) -> T
+    where F: Fn(&V3) -> T,
+          G: Fn(&V3) -> T
+{
+    move |pos| if pos.z < height { below(pos) } else { above(pos) }
+}
+
+/// Texture `flat` where the surface of `distance_f` faces `up` within the angle whose cosine is
+/// `min_cos`, and `steep` elsewhere.
+///
+/// Put snow on the flat parts of a mountain, or grass on top of a terrain and rock on its
+/// cliffs.
+pub fn by_normal<T, D, F, G>(distance_f: D,
+                             up: V3,
+                             min_cos: FP,
+                             flat: F,
+                             steep: G)
+                             -> impl Fn(&V3) -> T
+    where D: Fn(&V3) -> FP,
+          F: Fn(&V3) -> T,
+          G: Fn(&V3) -> T
+{
+    move |pos| {
+        let normal = pos.grad(|p| distance_f(&p));
+        if normal.dot(&up) >= min_cos {
+            flat(pos)
+        } else {
+            steep(pos)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use v::v3;
+    use shape::sphere_fn;
+    use Color;
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let m = Mapping::new(fp(2), v3(1, 0, 0));
+        assert!(m.apply(&v3(5, 4, -2)) == v3(2, 2, -1));
+
+        let c2 = checker2(m);
+        assert!(c2(&v3(1, 0, 0)) == fp(0));
+        assert!(c2(&v3(3, 0, 7)) == fp(1));
+        assert!(c2(&v3(3, 2, 7)) == fp(0));
+        let c3 = checker3(m);
+        assert!(c3(&v3(1, 0, 2)) == fp(1));
+        assert!(c3(&v3(-1, 0, 0)) == fp(1));
+
+        let s = stripes(Mapping::default());
+        assert!(s(&v3(0, 5, 5)) == fp(0) && s(&v3(1, 5, 5)) == fp(1));
+        let r = rings(Mapping::default());
+        assert!(r(&v3(0, 0, 9)) == fp(0) && r(&v3(0, 1, 0)) == fp(1) && r(&v3(2, 0, 0)) == fp(0));
+
+        let g = grid(Mapping::scale(fp(4)), fp(1) / fp(8));
+        assert!(g(&v3(4, 2, 0)) == fp(1));
+        assert!(g(&v3(2, 2, 0)) == fp(0));
+        let d = dots(Mapping::default(), fp(1) / fp(4));
+        assert!(d(&V3::new(fp(1) / fp(2), fp(1) / fp(2), fp(1) / fp(2))) == fp(1));
+        assert!(d(&v3(0, 0, 0)) == fp(0));
+
+        for &pattern in &[&marble(Mapping::scale(fp(4)), 1, fp(1)) as &dyn Fn(&V3) -> FP,
+                          &wood(Mapping::scale(fp(4)), 1, fp(1))] {
+            for i in 0..50 {
+                let v = pattern(&V3::new(FP(i * 97), FP(i * -31), FP(i * 13)));
+                assert!(v >= fp(0) && v <= fp(1));
+            }
+        }
+        // Without turbulence the veins are straight.
+        let plain = marble(Mapping::default(), 1, fp(0));
+        for &y in &[fp(0), fp(3), fp(-7)] {
+            assert!(plain(&V3::new(fp(1) / fp(2), y, fp(0))) > fp(31) / fp(32));
+            assert!(plain(&V3::new(fp(3) / fp(2), y, fp(0))) < fp(1) / fp(32));
+        }
+    }
+
+    #[test]
+    fn test_combinators() {
+        let pick = select(stripes(Mapping::default()), Color::Red, Color::Blue);
+        assert!(pick(&v3(0, 0, 0)) == Color::Red && pick(&v3(1, 0, 0)) == Color::Blue);
+
+        let red = Rgb::from(Color::Red);
+        let blue = Rgb::from(Color::Blue);
+        let ramp = gradient(|p: &V3| p.x / fp(4), red, blue);
+        assert!(ramp(&v3(2, 0, 0)) == red.mix(blue, fp(1) / fp(2)));
+        let mixed = mix(|_: &V3| red, &ramp, |_: &V3| fp(1));
+        assert!(mixed(&v3(4, 0, 0)) == blue);
+
+        let layers = by_height(fp(5), |_: &V3| Color::Green, |_: &V3| Color::White);
+        assert!(layers(&v3(0, 0, 0)) == Color::Green && layers(&v3(0, 0, 6)) == Color::White);
+
+        let hill = by_normal(sphere_fn(v3(0, 0, 0), fp(10)),
+                             v3(0, 0, 1),
+                             fp(1) / fp(2),
+                             |_: &V3| Color::White,
+                             |_: &V3| Color::Green);
+        assert!(hill(&v3(0, 0, 10)) == Color::White);
+        assert!(hill(&v3(10, 0, 0)) == Color::Green);
+    }
+}
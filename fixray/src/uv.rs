@@ -0,0 +1,109 @@
+//! Surface coordinates for putting images on shapes.
+//!
+//! The mappings take positions relative to the center of the shape. The ones for bounded shapes
+//! cover the surface once with coordinates between 0 and 1, with `v` going up.
+
+use fp::{fp, FP, PI, TAU};
+use v::V3;
+
+fn len2(x: FP, y: FP) -> FP {
+    (x * x + y * y).sqrt()
+}
+
+/// Coordinates from an offset between `-size` and `size`, the middle if there is no size.
+fn span(a: FP, size: FP) -> FP {
+    if size == fp(0) {
+        return fp(1) / fp(2);
+    }
+    (a / size + fp(1)) / fp(2)
+}
+
+/// Projection on the xy plane, in scene units.
+pub fn planar(pos: &V3) -> (FP, FP) {
+    (pos.x, pos.y)
+}
+
+/// Projection along the axis closest to `normal`, in scene units.
+///
+/// Flat surfaces facing x or y get the other horizontal axis as `u` and z as `v`.
+pub fn project(pos: &V3, normal: &V3) -> (FP, FP) {
+    let n = normal.map(|c| c.abs());
+    if n.z >= n.x && n.z >= n.y {
+        (pos.x, pos.y)
+    } else if n.x >= n.y {
+        (pos.y, pos.z)
+    } else {
+        (pos.x, pos.z)
+    }
+}
+
+/// Longitude and latitude around the z axis.
+pub fn spherical(pos: &V3) -> (FP, FP) {
+    let u = pos.y.atan2(pos.x) / TAU + fp(1) / fp(2);
+    let v = pos.z.atan2(len2(pos.x, pos.y)) / PI + fp(1) / fp(2);
+    (u, v)
+}
+
+/// Angle around the z axis and height between `-half_height` and `half_height`.
+pub fn cylindrical(pos: &V3, half_height: FP) -> (FP, FP) {
+    let u = pos.y.atan2(pos.x) / TAU + fp(1) / fp(2);
+    (u, span(pos.z, half_height))
+}
+
+/// Angle around the z axis and angle around the tube of a torus.
+pub fn toroidal(pos: &V3, major_radius: FP) -> (FP, FP) {
+    let u = pos.y.atan2(pos.x) / TAU + fp(1) / fp(2);
+    let v = pos.z.atan2(len2(pos.x, pos.y) - major_radius) / TAU + fp(1) / fp(2);
+    (u, v)
+}
+
+/// The whole image on each face of a box, projected along the axis of the face.
+///
+/// Boxes that are flat along an axis get the image on the two sides facing that axis.
+pub fn cube(pos: &V3, half_size: &V3) -> (FP, FP) {
+    let p = V3::new(span(pos.x, half_size.x), span(pos.y, half_size.y), span(pos.z, half_size.z));
+    // How far toward each pair of faces the position is, with flat sides all the way there.
+    let toward = |a: FP, size: FP| if size == fp(0) { fp(1) } else { a / size };
+    let scaled = V3::new(toward(pos.x, half_size.x),
+                         toward(pos.y, half_size.y),
+                         toward(pos.z, half_size.z));
+    project(&p, &scaled)
+}
+
+#[cfg(test)]
+mod tests {
+    use v::v3;
+    use super::*;
+
+    fn close(a: (FP, FP), b: (FP, FP)) -> bool {
+        (a.0 - b.0).abs() <= FP(4) && (a.1 - b.1).abs() <= FP(4)
+    }
+
+    #[test]
+    fn test_mappings() {
+        let half = fp(1) / fp(2);
+        assert!(planar(&v3(3, -2, 7)) == (fp(3), fp(-2)));
+        assert!(project(&v3(3, -2, 7), &v3(0, -1, 0)) == (fp(3), fp(7)));
+        assert!(project(&v3(3, -2, 7), &v3(2, 1, 1)) == (fp(-2), fp(7)));
+
+        assert!(close(spherical(&v3(0, -1, 0)), (fp(1) / fp(4), half)));
+        assert!(close(spherical(&v3(1, 0, 0)), (half, half)));
+        assert!(close(spherical(&v3(0, 1, 0)), (fp(3) / fp(4), half)));
+        assert!(close(spherical(&v3(0, 0, 5)), (half, fp(1))));
+        assert!(close(spherical(&v3(0, 0, -5)), (half, fp(0))));
+
+        assert!(close(cylindrical(&v3(0, -2, 3), fp(3)), (fp(1) / fp(4), fp(1))));
+        assert!(close(cylindrical(&v3(2, 0, 0), fp(3)), (half, half)));
+        assert!(close(toroidal(&v3(4, 0, 1), fp(4)), (half, fp(3) / fp(4))));
+        assert!(close(toroidal(&v3(0, 5, 0), fp(4)), (fp(3) / fp(4), half)));
+
+        let size = v3(2, 4, 8);
+        assert!(cube(&v3(2, 0, 0), &size) == (half, half));
+        assert!(cube(&v3(1, -4, 4), &size) == (fp(3) / fp(4), fp(3) / fp(4)));
+        assert!(cube(&v3(-2, -4, 8), &size) == (fp(0), fp(0)));
+
+        // Flat shapes don't divide by zero.
+        assert!(cube(&v3(1, -1, 0), &v3(2, 2, 0)) == (fp(3) / fp(4), fp(1) / fp(4)));
+        assert!(close(cylindrical(&v3(2, 0, 0), fp(0)), (half, half)));
+    }
+}
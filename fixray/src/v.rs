@@ -5,7 +5,7 @@ const I: V3 = v3(1, 0, 0);
 const J: V3 = v3(0, 1, 0);
 const K: V3 = v3(0, 0, 1);
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct V3 {
     pub x: FP,
     pub y: FP,
@@ -18,7 +18,7 @@ pub const fn v3(x: i32, y: i32, z: i32) -> V3 {
 
 impl V3 {
     pub const fn new(x: FP, y: FP, z: FP) -> V3 {
-        V3 { x: x, y: y, z: z }
+        V3 { x, y, z }
     }
 
     pub fn dot(&self, other: &V3) -> FP {
@@ -39,12 +39,30 @@ impl V3 {
         self.dot(self).sqrt()
     }
 
+    /// Apply a function to each component.
+    pub fn map<F>(&self, f: F) -> V3 where F: Fn(FP) -> FP {
+        V3::new(f(self.x), f(self.y), f(self.z))
+    }
+
+    /// Scalar field gradient, by central differences.
+    pub fn slope<F>(&self, f: F) -> V3 where F: Fn(V3) -> FP {
+        let h = EPSILON * fp(2);
+        V3::new(
+            (f(*self + I * EPSILON) - f(*self - I * EPSILON)) / h,
+            (f(*self + J * EPSILON) - f(*self - J * EPSILON)) / h,
+            (f(*self + K * EPSILON) - f(*self - K * EPSILON)) / h)
+    }
+
     /// Normalized scalar field gradient.
+    ///
+    /// A flat field has no direction and gives a zero vector.
     pub fn grad<F>(&self, f: F) -> V3 where F: Fn(V3) -> FP {
-        V3::new(
-            f(*self + I * EPSILON) - f(*self - I * EPSILON),
-            f(*self + J * EPSILON) - f(*self - J * EPSILON),
-            f(*self + K * EPSILON) - f(*self - K * EPSILON))
+        let g = self.slope(f);
+        if g.dot(&g) == fp(0) {
+            g
+        } else {
+            g.normalized()
+        }
     }
 }
 
@@ -0,0 +1,600 @@
+//! Bytecode for distance expressions.
+//!
+//! A `Program` is a distance function stored as data, so it can be sent to the device and
+//! evaluated without reflashing. The bytecode runs on two stacks, one of positions and one of
+//! distances. Shape instructions push the distance to the shape at the top position, CSG
+//! instructions combine the top distances, and transform and domain instructions push a new
+//! position derived from the top one until a matching `POP`.
+//!
+//! Programs are written with a `Builder`, which keeps the stacks balanced. A scale has to scale
+//! the distance back as well, so the expression inside it must come down to a single distance.
+//!
+//! ```text
+//! SHAPE shape          push distance to shape, encoded as in the binary scene format
+//! UNION                pop two distances, push the minimum
+//! INTERSECT            pop two distances, push the maximum
+//! SUBTRACT             pop b and a, push a with b cut out of it
+//! SMOOTH_UNION k:fp    union blended over distance k
+//! ADD k:fp             add k to the top distance
+//! MUL k:fp             multiply the top distance by k
+//! TRANSLATE v:v3       push position moved by -v
+//! ROTATE axis:u8 a:fp  push position rotated by -a radians around x, y or z
+//! SCALE s:fp           push position divided by s, s > 0
+//! REPEAT period:v3     push position folded into a repetition cell
+//! MIRROR n:v3 d:fp     push position folded across a plane
+//! POLAR count:u8       push position folded into a polar sector around the z axis
+//! POP                  pop position
+//! ```
+
+use core::cmp::{max, min};
+
+use fp::{fp, FP, TAU};
+use v::V3;
+use binary::{self, Encoder, Reader};
+use geom::Transform;
+use ops::{fold_mirror, fold_polar, fold_repeat};
+use scene::{Body, SurfaceContext};
+use shape::Shape;
+use {Material, Paint};
+
+/// Depth of the distance stack.
+pub const MAX_VALUES: usize = 16;
+/// Depth of the position stack, including the query position.
+pub const MAX_POSITIONS: usize = 8;
+
+const SHAPE: u8 = 0x01;
+const UNION: u8 = 0x10;
+const INTERSECT: u8 = 0x11;
+const SUBTRACT: u8 = 0x12;
+const SMOOTH_UNION: u8 = 0x13;
+const ADD: u8 = 0x18;
+const MUL: u8 = 0x19;
+const TRANSLATE: u8 = 0x20;
+const ROTATE: u8 = 0x21;
+const SCALE: u8 = 0x22;
+const REPEAT: u8 = 0x23;
+const MIRROR: u8 = 0x24;
+const POLAR: u8 = 0x25;
+const POP: u8 = 0x2f;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    /// Building ran out of buffer space.
+    BufferFull,
+    /// Code ended in the middle of an instruction.
+    Truncated,
+    BadOpcode(u8),
+    /// Bad operand value, like an unknown shape, a flat ellipsoid or a zero scale.
+    BadValue(u8),
+    StackOverflow,
+    StackUnderflow,
+    /// Program didn't end with exactly one distance and the query position on the stacks, or a
+    /// scale didn't close over exactly one distance.
+    Unbalanced,
+}
+
+impl From<binary::Error> for Error {
+    fn from(e: binary::Error) -> Error {
+        match e {
+            binary::Error::BufferFull => Error::BufferFull,
+            binary::Error::BadValue(x) => Error::BadValue(x),
+            _ => Error::Truncated,
+        }
+    }
+}
+
+/// Distance function interpreted from bytecode.
+pub struct Program<'a> {
+    code: &'a [u8],
+    paint: Paint,
+}
+
+impl<'a> Program<'a> {
+    /// Validate code and wrap it into a body.
+    pub fn new(code: &'a [u8], paint: Paint) -> Result<Program<'a>, Error> {
+        let program = Program {
+            code,
+            paint,
+        };
+        // There are no jumps, so a single run goes through every instruction.
+        program.run(&V3::new(fp(0), fp(0), fp(0)))?;
+        Ok(program)
+    }
+
+    fn run(&self, pos: &V3) -> Result<FP, Error> {
+        let mut values: Stack<FP, MAX_VALUES> = Stack::new(fp(0));
+        let mut positions: Stack<V3, MAX_POSITIONS> = Stack::new(*pos);
+        positions.push(*pos)?;
+
+        let mut code = Reader {
+            data: self.code,
+            pos: 0,
+        };
+
+        while !code.is_empty() {
+            let p = positions.top()?;
+
+            match code.u8()? {
+                SHAPE => values.push(code.shape()?.distance(&p))?,
+                op @ UNION | op @ INTERSECT | op @ SUBTRACT | op @ SMOOTH_UNION => {
+                    let b = values.pop()?;
+                    let a = values.pop()?;
+                    values.push(match op {
+                            UNION => min(a, b),
+                            INTERSECT => max(a, b),
+                            SUBTRACT => max(a, -b),
+                            _ => smooth_min(a, b, code.fp()?),
+                        })?;
+                }
+                ADD => {
+                    let a = values.pop()?;
+                    values.push(a + code.fp()?)?;
+                }
+                MUL => {
+                    let a = values.pop()?;
+                    values.push(a * code.fp()?)?;
+                }
+                TRANSLATE => positions.push(p - code.v3()?)?,
+                ROTATE => {
+                    let axis = code.u8()?;
+                    let angle = code.fp()?;
+                    let t = Transform::identity();
+                    let t = match axis {
+                        0 => t.rotate_x(angle),
+                        1 => t.rotate_y(angle),
+                        2 => t.rotate_z(angle),
+                        x => return Err(Error::BadValue(x)),
+                    };
+                    positions.push(t.to_local(&p))?;
+                }
+                SCALE => {
+                    let s = code.fp()?;
+                    if s <= fp(0) {
+                        return Err(Error::BadValue(0));
+                    }
+                    positions.push(Transform::identity().scale(s).to_local(&p))?;
+                }
+                REPEAT => {
+                    let period = code.v3()?;
+                    positions.push(fold_repeat(&p,
+                                          &period,
+                                          &[i32::MIN; 3],
+                                          &[i32::MAX; 3]))?;
+                }
+                MIRROR => {
+                    let normal = code.v3()?;
+                    let offset = code.fp()?;
+                    positions.push(fold_mirror(&p, &normal, offset))?;
+                }
+                POLAR => {
+                    let count = code.u8()?;
+                    if count == 0 {
+                        return Err(Error::BadValue(0));
+                    }
+                    let sector = TAU / fp(count as i32);
+                    positions.push(fold_polar(&p, &V3::new(fp(0), fp(0), fp(0)), sector))?;
+                }
+                POP => {
+                    if positions.len == 1 {
+                        return Err(Error::StackUnderflow);
+                    }
+                    positions.pop()?;
+                }
+                x => return Err(Error::BadOpcode(x)),
+            }
+        }
+
+        if values.len != 1 || positions.len != 1 {
+            return Err(Error::Unbalanced);
+        }
+        values.pop()
+    }
+}
+
+/// Fixed-size evaluation stack.
+struct Stack<T, const N: usize> {
+    items: [T; N],
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> Stack<T, N> {
+    fn new(fill: T) -> Stack<T, N> {
+        Stack {
+            items: [fill; N],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, x: T) -> Result<(), Error> {
+        if self.len == N {
+            return Err(Error::StackOverflow);
+        }
+        self.items[self.len] = x;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<T, Error> {
+        let x = self.top()?;
+        self.len -= 1;
+        Ok(x)
+    }
+
+    fn top(&self) -> Result<T, Error> {
+        if self.len == 0 {
+            return Err(Error::StackUnderflow);
+        }
+        Ok(self.items[self.len - 1])
+    }
+}
+
+impl<'a> Body for Program<'a> {
+    fn distance(&self, pos: &V3) -> FP {
+        // The code was validated on construction.
+        self.run(pos).unwrap_or(FP(i32::MAX))
+    }
+
+    fn material(&self, ctx: &SurfaceContext) -> Material {
+        self.paint.material(ctx)
+    }
+}
+
+/// Polynomial smooth minimum.
+fn smooth_min(a: FP, b: FP, k: FP) -> FP {
+    if k <= fp(0) {
+        return min(a, b);
+    }
+    let h = (fp(1) / fp(2) + (b - a) / (fp(2) * k)).clamp(fp(0), fp(1));
+    b + (a - b) * h - k * h * (fp(1) - h)
+}
+
+/// Writer for programs.
+///
+/// The methods chain and remember the first error, which `finish` reports. Every transform or
+/// domain operation must be closed with `end` after the expression it applies to.
+pub struct Builder<'a> {
+    enc: Encoder<'a>,
+    /// Scale factors of the open position operations and the number of distances at their start.
+    open: [(Option<FP>, usize); MAX_POSITIONS],
+    depth: usize,
+    /// Number of distances on the stack.
+    values: usize,
+    error: Option<Error>,
+}
+
+impl<'a> Builder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Builder<'a> {
+        Builder {
+            enc: Encoder::bare(buf),
+            open: [(None, 0); MAX_POSITIONS],
+            depth: 0,
+            values: 0,
+            error: None,
+        }
+    }
+
+    /// Return the length of the finished program.
+    pub fn finish(self) -> Result<usize, Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.depth > 0 || self.values != 1 {
+            return Err(Error::Unbalanced);
+        }
+        Ok(self.enc.len())
+    }
+
+    pub fn shape(&mut self, shape: &Shape) -> &mut Builder<'a> {
+        self.values += 1;
+        self.emit(|enc| {
+            enc.u8(SHAPE)?;
+            enc.shape(shape)
+        })
+    }
+
+    pub fn union(&mut self) -> &mut Builder<'a> {
+        self.combine(UNION)
+    }
+
+    pub fn intersect(&mut self) -> &mut Builder<'a> {
+        self.combine(INTERSECT)
+    }
+
+    /// Cut the last expression out of the one before it.
+    pub fn subtract(&mut self) -> &mut Builder<'a> {
+        self.combine(SUBTRACT)
+    }
+
+    pub fn smooth_union(&mut self, k: FP) -> &mut Builder<'a> {
+        if !self.pop_values(2) {
+            return self;
+        }
+        self.emit(|enc| {
+            enc.u8(SMOOTH_UNION)?;
+            enc.fp(k)
+        })
+    }
+
+    /// Grow the last expression by `radius`, rounding its edges.
+    pub fn round(&mut self, radius: FP) -> &mut Builder<'a> {
+        if !self.pop_values(1) {
+            return self;
+        }
+        self.emit(|enc| {
+            enc.u8(ADD)?;
+            enc.fp(-radius)
+        })
+    }
+
+    pub fn translate(&mut self, offset: V3) -> &mut Builder<'a> {
+        self.open(None, |enc| {
+            enc.u8(TRANSLATE)?;
+            enc.v3(&offset)
+        })
+    }
+
+    /// Rotate around the x axis by an angle in radians.
+    pub fn rotate_x(&mut self, angle: FP) -> &mut Builder<'a> {
+        self.rotate(0, angle)
+    }
+
+    /// Rotate around the y axis by an angle in radians.
+    pub fn rotate_y(&mut self, angle: FP) -> &mut Builder<'a> {
+        self.rotate(1, angle)
+    }
+
+    /// Rotate around the z axis by an angle in radians.
+    pub fn rotate_z(&mut self, angle: FP) -> &mut Builder<'a> {
+        self.rotate(2, angle)
+    }
+
+    pub fn scale(&mut self, scale: FP) -> &mut Builder<'a> {
+        self.open(Some(scale), |enc| {
+            enc.u8(SCALE)?;
+            enc.fp(scale)
+        })
+    }
+
+    pub fn repeat(&mut self, period: V3) -> &mut Builder<'a> {
+        self.open(None, |enc| {
+            enc.u8(REPEAT)?;
+            enc.v3(&period)
+        })
+    }
+
+    pub fn mirror(&mut self, normal: V3, offset: FP) -> &mut Builder<'a> {
+        self.open(None, |enc| {
+            enc.u8(MIRROR)?;
+            enc.v3(&normal)?;
+            enc.fp(offset)
+        })
+    }
+
+    /// Repeat around the z axis `count` times.
+    pub fn polar(&mut self, count: u8) -> &mut Builder<'a> {
+        self.open(None, |enc| {
+            enc.u8(POLAR)?;
+            enc.u8(count)
+        })
+    }
+
+    /// Close the latest transform or domain operation.
+    pub fn end(&mut self) -> &mut Builder<'a> {
+        if self.depth == 0 {
+            self.error = self.error.or(Some(Error::StackUnderflow));
+            return self;
+        }
+        self.depth -= 1;
+        let (scale, values) = self.open[self.depth];
+        // Only the top distance gets scaled back.
+        if scale.is_some() && self.values != values + 1 {
+            self.error = self.error.or(Some(Error::Unbalanced));
+            return self;
+        }
+        self.emit(|enc| {
+            enc.u8(POP)?;
+            if let Some(s) = scale {
+                enc.u8(MUL)?;
+                enc.fp(s)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn combine(&mut self, op: u8) -> &mut Builder<'a> {
+        if !self.pop_values(2) {
+            return self;
+        }
+        self.emit(|enc| enc.u8(op))
+    }
+
+    /// Replace the top `n` distances with one, or record an underflow if there aren't enough.
+    fn pop_values(&mut self, n: usize) -> bool {
+        if self.values < n {
+            self.error = self.error.or(Some(Error::StackUnderflow));
+            return false;
+        }
+        self.values -= n - 1;
+        true
+    }
+
+    fn rotate(&mut self, axis: u8, angle: FP) -> &mut Builder<'a> {
+        self.open(None, |enc| {
+            enc.u8(ROTATE)?;
+            enc.u8(axis)?;
+            enc.fp(angle)
+        })
+    }
+
+    fn open<F>(&mut self, scale: Option<FP>, f: F) -> &mut Builder<'a>
+        where F: FnOnce(&mut Encoder) -> Result<(), binary::Error>
+    {
+        if self.depth + 1 == MAX_POSITIONS {
+            self.error = self.error.or(Some(Error::StackOverflow));
+            return self;
+        }
+        self.open[self.depth] = (scale, self.values);
+        self.depth += 1;
+        self.emit(f)
+    }
+
+    fn emit<F>(&mut self, f: F) -> &mut Builder<'a>
+        where F: FnOnce(&mut Encoder) -> Result<(), binary::Error>
+    {
+        if self.error.is_none() {
+            if let Err(e) = f(&mut self.enc) {
+                self.error = Some(e.into());
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp::{fp, FP, HALF_PI};
+    use v::v3;
+    use scene::Body;
+    use shape::{Shape, box_fn, sphere_fn};
+    use {Material, Paint};
+    use super::*;
+
+    fn ball(radius: FP) -> Shape {
+        Shape::Sphere {
+            center: v3(0, 0, 0),
+            radius,
+        }
+    }
+
+    fn compile<F: Fn(&mut Builder)>(buf: &mut [u8], f: F) -> Result<usize, Error> {
+        let mut b = Builder::new(buf);
+        f(&mut b);
+        b.finish()
+    }
+
+    #[test]
+    fn test_program() {
+        let mut buf = [0u8; 256];
+        let len = compile(&mut buf, |b| {
+                b.translate(v3(5, 0, 0))
+                    .shape(&ball(fp(1)))
+                    .end()
+                    .shape(&Shape::Box {
+                        center: v3(0, 0, 0),
+                        half_size: v3(1, 1, 1),
+                    })
+                    .union()
+                    .scale(fp(2))
+                    .shape(&ball(fp(1)))
+                    .translate(v3(0, 0, 4))
+                    .shape(&ball(fp(1)))
+                    .end()
+                    .union()
+                    .end()
+                    .subtract();
+            })
+            .ok()
+            .unwrap();
+        let program = Program::new(&buf[..len], Paint::Solid(Material::Mirror)).ok().unwrap();
+
+        let ball = sphere_fn(v3(5, 0, 0), fp(1));
+        let cube = box_fn(v3(0, 0, 0), v3(1, 1, 1));
+        let hole = sphere_fn(v3(0, 0, 0), fp(2));
+        let top_hole = sphere_fn(v3(0, 0, 8), fp(2));
+        for &p in &[v3(5, 0, 0), v3(0, 0, 3), v3(2, 2, 2), v3(1, 1, 1), v3(7, -1, 0), v3(1, 0, 8)] {
+            let expected = max(min(ball(&p), cube(&p)), -min(hole(&p), top_hole(&p)));
+            assert!((program.distance(&p) - expected).abs() <= FP(2));
+        }
+    }
+
+    #[test]
+    fn test_domain_ops() {
+        let mut buf = [0u8; 256];
+        let len = compile(&mut buf, |b| {
+                b.repeat(v3(10, 0, 0))
+                    .rotate_z(HALF_PI)
+                    .shape(&Shape::Box {
+                        center: v3(0, 0, 0),
+                        half_size: v3(4, 1, 1),
+                    })
+                    .end()
+                    .end();
+            })
+            .ok()
+            .unwrap();
+        let program = Program::new(&buf[..len], Paint::Solid(Material::Mirror)).ok().unwrap();
+        // The rotated box is long along y.
+        assert!((program.distance(&v3(20, 3, 0)) - fp(-1)).abs() <= FP(4));
+        assert!((program.distance(&v3(-23, 0, 0)) - fp(2)).abs() <= FP(4));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut buf = [0u8; 256];
+        assert!(compile(&mut buf, |b| {
+                b.union();
+            })
+            .err() == Some(Error::StackUnderflow));
+        assert!(compile(&mut buf, |b| {
+                b.shape(&ball(fp(1))).smooth_union(fp(1));
+            })
+            .err() == Some(Error::StackUnderflow));
+        assert!(compile(&mut buf, |b| {
+                b.round(fp(1));
+            })
+            .err() == Some(Error::StackUnderflow));
+        assert!(Program::new(&[UNION], Paint::Solid(Material::Mirror)).err() ==
+                Some(Error::StackUnderflow));
+
+        // Every distance has to be combined into one.
+        assert!(compile(&mut buf, |b| {
+                b.shape(&ball(fp(1))).shape(&ball(fp(2)));
+            })
+            .err() == Some(Error::Unbalanced));
+        assert!(compile(&mut buf, |_| {}).err() == Some(Error::Unbalanced));
+
+        assert!(compile(&mut buf, |b| {
+                b.translate(v3(1, 0, 0)).shape(&ball(fp(1)));
+            })
+            .err() == Some(Error::Unbalanced));
+        assert!(compile(&mut buf, |b| {
+                b.end();
+            })
+            .err() == Some(Error::StackUnderflow));
+        // Two distances under one scale.
+        assert!(compile(&mut buf, |b| {
+                b.scale(fp(2)).shape(&ball(fp(1))).shape(&ball(fp(2))).end().union();
+            })
+            .err() == Some(Error::Unbalanced));
+        assert!(compile(&mut buf[..4], |b| {
+                b.shape(&ball(fp(1)));
+            })
+            .err() == Some(Error::BufferFull));
+
+        assert!(Program::new(&[], Paint::Solid(Material::Mirror)).err() == Some(Error::Unbalanced));
+        assert!(Program::new(&[0x99], Paint::Solid(Material::Mirror)).err() ==
+                Some(Error::BadOpcode(0x99)));
+        assert!(Program::new(&[SHAPE, 0], Paint::Solid(Material::Mirror)).err() ==
+                Some(Error::Truncated));
+        assert!(Program::new(&[SHAPE, 0xee], Paint::Solid(Material::Mirror)).err() ==
+                Some(Error::BadValue(0xee)));
+
+        let len = compile(&mut buf, |b| {
+                b.scale(fp(-1)).shape(&ball(fp(1))).end();
+            })
+            .ok()
+            .unwrap();
+        assert!(Program::new(&buf[..len], Paint::Solid(Material::Mirror)).err() ==
+                Some(Error::BadValue(0)));
+        let len = compile(&mut buf, |b| {
+                b.shape(&Shape::Ellipsoid {
+                    center: v3(0, 0, 0),
+                    radii: v3(2, 0, 1),
+                });
+            })
+            .ok()
+            .unwrap();
+        assert!(Program::new(&buf[..len], Paint::Solid(Material::Mirror)).err() ==
+                Some(Error::BadValue(10)));
+    }
+}
@@ -29,26 +29,22 @@ pub extern "C" fn main() -> ! {
     vga.clear();
 
     use fixray::*;
-    use fixray::Color::*;
-
-    let scene = Scene
-        + Object::new(sphere_fn(v3(10, 5, 2), fp(3)), m(Material::Surface(Yellow, Red, Black)))
-        + Object::new(sphere_fn(v3(5, 10, 2), fp(3)), m(Material::Mirror))
-        + Object::new(sphere_fn(v3(0, 15, 2), fp(3)), m(Material::Surface(Yellow, Red, Black)))
-        + Object::new(plane_fn(v3(0, 0, 1), fp(0)), checkerboard(Material::Surface(Green, Green, Black), Material::Surface(White, White, Black)))
-        ;
-
-    let frustum = Frustum {
-        origin: v3(0, 0, 4),
-        dir: v3(8, 4, -1).normalized(),
-        up: v3(0, 0, 1),
-    };
 
-    let light_dir = v3(1, 1, -4).normalized();
+    let desc = match text::parse::<8>(include_bytes!("../fixray/scenes/balls.txt")) {
+        Ok(desc) => desc,
+        Err(e) => {
+            vga.puts("Scene error at line ");
+            vga.puti(e.line as i32);
+            vga.puts(" column ");
+            vga.puti(e.column as i32);
+            hang();
+        }
+    };
 
-    vga.draw_screen(|x, y| {
-        trace(&scene, frustum.ray(x, y), &light_dir)
-    });
+    let settings = TraceSettings::fast();
+    vga.draw_screen(dither::ordered(|x, y| {
+        trace_rgb(&desc, desc.camera.ray(x, y), &desc.lights, &settings)
+    }));
 
     loop {
     }
@@ -57,6 +57,10 @@ impl Vga {
         puts(PORT, s);
     }
 
+    pub fn puti(&self, i: i32) {
+        puti(PORT, i);
+    }
+
     pub fn graphics_mode(&self, text_up_to: u32) {
         debug_assert!(text_up_to <= 16);
         puts(PORT, "\x1b[");